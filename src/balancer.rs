@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use crate::config::{ConfigEndpoint, ConfigOrigin};
+use crate::health::is_healthy;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalancingStrategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    Random,
+    IpHash
+}
+
+/// Current weights of the smooth weighted round-robin by the position of the origin in its endpoint, keyed by
+/// the endpoint. Origins of a wildcard endpoint differ by the hostname, but they share the state of the endpoint.
+static ROUND_ROBIN: Lazy<Mutex<HashMap<String, Vec<i64>>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// Number of live connections forwarded to each origin.
+static CONNECTIONS: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+pub fn connection_opened(origin: &str) {
+    *CONNECTIONS.lock().unwrap().entry(origin.to_string()).or_insert(0) += 1;
+}
+
+pub fn connection_closed(origin: &str) {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    if let Some(count) = connections.get_mut(origin) {
+        *count -= 1;
+        if *count == 0 {
+            connections.remove(origin);
+        }
+    }
+}
+
+pub fn connection_count(origin: &str) -> usize {
+    CONNECTIONS.lock().unwrap().get(origin).copied().unwrap_or(0)
+}
+
+/// Selects an origin of the endpoint using its balancing strategy. Origins with zero weight, unhealthy origins
+/// and origins in `exclude` (which already failed for the connection) are never selected.
+pub fn select_origin(endpoint: &ConfigEndpoint, client_ip: IpAddr, exclude: &[String]) -> Option<String> {
+    let (positions, origins): (Vec<usize>, Vec<ConfigOrigin>) = endpoint.origins().into_iter()
+        .enumerate()
+        .filter(|(_, origin)| origin.weight > 0 && is_healthy(&origin.address) && !exclude.contains(&origin.address))
+        .unzip();
+    let index = match origins.len() {
+        0 => return None,
+        1 => 0,
+        _ => match endpoint.strategy {
+            BalancingStrategy::RoundRobin => select_round_robin(&round_robin_key(endpoint), &origins, &positions),
+            BalancingStrategy::LeastConnections => select_least_connections(&origins),
+            BalancingStrategy::Random => select_random(&origins),
+            BalancingStrategy::IpHash => select_ip_hash(&origins, client_ip)
+        }
+    };
+    Some(origins[index].address.clone())
+}
+
+fn round_robin_key(endpoint: &ConfigEndpoint) -> String {
+    match endpoint.forge {
+        true => format!("{} (forge)", endpoint.name()),
+        false => endpoint.name().to_string()
+    }
+}
+
+/// Forgets the round-robin state of the endpoints, whose origins may have changed.
+pub fn reset_round_robin(names: &[String]) {
+    ROUND_ROBIN.lock().unwrap().retain(|key, _| {
+        !names.iter().any(|name| *key == *name || *key == format!("{} (forge)", name))
+    });
+}
+
+/// `positions` are the positions of the origins in their endpoint, the origins left out are not selected.
+fn select_round_robin(key: &str, origins: &[ConfigOrigin], positions: &[usize]) -> usize {
+    let mut round_robin = ROUND_ROBIN.lock().unwrap();
+    let weights = round_robin.entry(key.to_string()).or_default();
+    let size = positions.iter().max().map_or(0, |position| position + 1);
+    if weights.len() < size {
+        weights.resize(size, 0);
+    }
+    
+    let total: i64 = origins.iter().map(|origin| origin.weight as i64).sum();
+    let mut selected = 0;
+    for (i, origin) in origins.iter().enumerate() {
+        weights[positions[i]] += origin.weight as i64;
+        if weights[positions[i]] > weights[positions[selected]] {
+            selected = i;
+        }
+    }
+    weights[positions[selected]] -= total;
+    selected
+}
+
+fn select_least_connections(origins: &[ConfigOrigin]) -> usize {
+    let connections = CONNECTIONS.lock().unwrap();
+    let load = |origin: &ConfigOrigin| connections.get(&origin.address).copied().unwrap_or(0) as u64;
+    
+    // compare connections per weight without division, first origin wins on a tie
+    let mut selected = 0;
+    for (i, origin) in origins.iter().enumerate().skip(1) {
+        let best = &origins[selected];
+        if load(origin) * (best.weight as u64) < load(best) * (origin.weight as u64) {
+            selected = i;
+        }
+    }
+    selected
+}
+
+fn select_random(origins: &[ConfigOrigin]) -> usize {
+    let total: u64 = origins.iter().map(|origin| origin.weight as u64).sum();
+    let mut point = rand::random_range(0..total);
+    for (i, origin) in origins.iter().enumerate() {
+        if point < origin.weight as u64 {
+            return i
+        }
+        point -= origin.weight as u64;
+    }
+    origins.len() - 1
+}
+
+/// Weighted rendezvous hashing, a client keeps its origin as long as the origin stays in the list.
+fn select_ip_hash(origins: &[ConfigOrigin], client_ip: IpAddr) -> usize {
+    let score = |origin: &ConfigOrigin| {
+        let mut hasher = DefaultHasher::new();
+        client_ip.hash(&mut hasher);
+        origin.address.hash(&mut hasher);
+        // map the hash into (0, 1) and scale it by the weight
+        let point = ((hasher.finish() >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        origin.weight as f64 / -point.ln()
+    };
+    
+    let mut selected = 0;
+    let mut selected_score = score(&origins[0]);
+    for (i, origin) in origins.iter().enumerate().skip(1) {
+        let origin_score = score(origin);
+        if origin_score > selected_score {
+            selected = i;
+            selected_score = origin_score;
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    
+    fn create_origins(weights: &[u32]) -> Vec<ConfigOrigin> {
+        weights.iter().enumerate().map(|(i, weight)| ConfigOrigin {
+            address: format!("test-{}-{}:25565", weights.len(), i),
+            weight: *weight
+        }).collect()
+    }
+    
+    #[test]
+    fn check_round_robin_weights() {
+        let origins = create_origins(&[5, 1, 1]);
+        let mut counts = [0; 3];
+        for _ in 0..14 {
+            counts[select_round_robin("round-robin-test", &origins, &[0, 1, 2])] += 1;
+        }
+        assert_eq!(counts, [10, 2, 2]);
+    }
+    
+    #[test]
+    fn check_round_robin_key() {
+        let config = test_config(r#"
+endpoints:
+  - hostname: "*.rr.example.net"
+    origins: ["{1}-a.internal:25565", "{1}-b.internal:25565"]
+"#);
+        let client_ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let origins: Vec<String> = ["a", "b", "c", "d"].iter()
+            .map(|sub| config.find_endpoint(format!("{}.rr.example.net", sub)).unwrap())
+            .map(|endpoint| select_origin(&endpoint, client_ip, &[]).unwrap())
+            .collect();
+        // subdomains take turns like a single hostname and do not add any state
+        assert_eq!(origins, ["a-a.internal:25565", "b-b.internal:25565", "c-a.internal:25565", "d-b.internal:25565"]);
+        assert_eq!(ROUND_ROBIN.lock().unwrap().keys().filter(|key| key.contains("rr.example.net")).count(), 1);
+        
+        reset_round_robin(&[String::from("*.rr.example.net")]);
+        assert!(!ROUND_ROBIN.lock().unwrap().keys().any(|key| key.contains("rr.example.net")));
+    }
+    
+    #[test]
+    fn check_least_connections() {
+        let origins = create_origins(&[2, 1]);
+        connection_opened(&origins[0].address);
+        connection_opened(&origins[0].address);
+        connection_opened(&origins[1].address);
+        assert_eq!(select_least_connections(&origins), 0);
+        
+        connection_opened(&origins[0].address);
+        assert_eq!(select_least_connections(&origins), 1);
+        assert_eq!(connection_count(&origins[0].address), 3);
+    }
+    
+    #[test]
+    fn check_ip_hash_stability() {
+        let origins = create_origins(&[1, 1, 1, 1]);
+        let client_ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let selected = select_ip_hash(&origins, client_ip);
+        assert_eq!(select_ip_hash(&origins, client_ip), selected);
+        
+        // removing another origin must not move the client
+        let mut remaining = origins.clone();
+        remaining.remove((selected + 1) % origins.len());
+        assert_eq!(remaining[select_ip_hash(&remaining, client_ip)].address, origins[selected].address);
+    }
+}
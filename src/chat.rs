@@ -1,6 +1,6 @@
 use std::fmt::Display;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 
 #[derive(Deserialize, Serialize)]
 pub struct ChatData {
@@ -33,6 +33,22 @@ impl ChatData {
 
 impl Display for ChatData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", json!(self).to_string())
+        write!(f, "{}", json!(self))
+    }
+}
+
+/// Flattens a text component (a string, an object or an array of components) into plain text.
+pub fn to_plain_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(components) => components.iter().map(to_plain_text).collect(),
+        Value::Object(object) => {
+            let mut text = object.get("text").map(to_plain_text).unwrap_or_default();
+            if let Some(extra) = object.get("extra") {
+                text.push_str(&to_plain_text(extra));
+            }
+            text
+        }
+        _ => String::new()
     }
 }
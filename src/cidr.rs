@@ -0,0 +1,102 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use serde::Deserialize;
+
+/// IPv4 or IPv6 network, a plain address is a network with a single address.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    pub network: IpAddr,
+    pub prefix: u8
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full_bytes = (prefix / 8) as usize;
+    if network[..full_bytes] != ip[..full_bytes] {
+        return false
+    }
+    
+    let remaining_bits = prefix % 8;
+    if remaining_bits == 0 {
+        return true
+    }
+    let mask = 0xFFu8 << (8 - remaining_bits);
+    (network[full_bytes] & mask) == (ip[full_bytes] & mask)
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+    
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+    
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None)
+        };
+        
+        let network: IpAddr = address.trim().parse().map_err(|_| format!("invalid address {}", value))?;
+        let network = network.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().ok().filter(|prefix| *prefix <= max_prefix)
+                .ok_or(format!("invalid prefix length in {}", value))?,
+            None => max_prefix
+        };
+        
+        Ok(Cidr {
+            network,
+            prefix
+        })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_contains() {
+        let cidr: Cidr = "10.1.0.0/17".parse().unwrap();
+        assert!(cidr.contains("10.1.127.255".parse().unwrap()));
+        assert!(!cidr.contains("10.1.128.0".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.1.0.1".parse().unwrap()));
+        
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains("2001:db8:ffff::1".parse().unwrap()));
+        assert!(!cidr.contains("2001:db9::1".parse().unwrap()));
+        assert!(!cidr.contains("10.1.0.1".parse().unwrap()));
+        
+        let cidr: Cidr = "192.0.2.7".parse().unwrap();
+        assert_eq!(cidr.prefix, 32);
+        assert!(cidr.contains("192.0.2.7".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("203.0.113.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+use crate::config::{DEFAULT_CONFIG_PATH, VERSION_PROTOCOL, VERSION_PROTOCOL_NAME, VERSION_PROXY_NAME};
+
+/// Commented example config printed by `print-default-config`.
+pub const DEFAULT_CONFIG: &str = include_str!("../config.example.yaml");
+
+pub const USAGE: &str = "Usage: pistonproxy [options] [command]
+
+Commands:
+  run                     Starts the proxy (default)
+  check-config            Validates the config and exits
+  print-default-config    Prints an example config
+  version                 Prints the version and the supported protocols
+  help                    Prints this message
+
+Options:
+  -c, --config <path>     Path of the config file, ./config.yaml by default
+  -l, --listen <address>  Port or address to listen on instead of the listen setting
+  -h, --help              Prints this message
+  -V, --version           Prints the version";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    CheckConfig,
+    PrintDefaultConfig,
+    Version,
+    Help
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub config: String,
+    /// Address the proxy listens on instead of the `listen` port of the config
+    pub listen: Option<SocketAddr>
+}
+
+impl Cli {
+    /// Parses the arguments following the program name.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Cli, String> {
+        let mut command = None;
+        let mut config = None;
+        let mut listen = None;
+        while let Some(arg) = args.next() {
+            // options may be written as `--name value` or `--name=value`
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None)
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next()).ok_or(format!("missing value of {}", name));
+            match name.as_str() {
+                "-c" | "--config" => config = Some(value()?),
+                "-l" | "--listen" => listen = Some(parse_listen(&value()?)?),
+                "-h" | "--help" => command = Some(Command::Help),
+                "-V" | "--version" => command = Some(Command::Version),
+                option if option.starts_with('-') => return Err(format!("unknown option {}", option)),
+                _ if command.is_some() => return Err(format!("unexpected argument {}", arg)),
+                "run" => command = Some(Command::Run),
+                "check-config" => command = Some(Command::CheckConfig),
+                "print-default-config" => command = Some(Command::PrintDefaultConfig),
+                "version" => command = Some(Command::Version),
+                "help" => command = Some(Command::Help),
+                _ => return Err(format!("unknown command {}", arg))
+            }
+        }
+        Ok(Cli {
+            command: command.unwrap_or(Command::Run),
+            config: config.unwrap_or(DEFAULT_CONFIG_PATH.to_string()),
+            listen
+        })
+    }
+}
+
+/// Listen address is either a port on all interfaces or a full socket address.
+fn parse_listen(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = value.parse::<u16>() {
+        return Ok(SocketAddr::from(([0, 0, 0, 0], port)))
+    }
+    value.parse().map_err(|_| format!("invalid listen address {}", value))
+}
+
+pub fn version_text() -> String {
+    format!(
+        "pistonproxy {}\nprotocol {} ({}) used for status pings of origins\n\
+        clients of 1.7 and newer are forwarded with any protocol version, server list pings of Beta 1.8 to 1.6 are answered",
+        VERSION_PROXY_NAME,
+        VERSION_PROTOCOL_NAME,
+        VERSION_PROTOCOL
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+    
+    #[test]
+    fn check_arguments() {
+        assert_eq!(parse(&[]).unwrap(), Cli {
+            command: Command::Run,
+            config: DEFAULT_CONFIG_PATH.to_string(),
+            listen: None
+        });
+        assert_eq!(parse(&["--config", "/etc/pistonproxy.yaml", "check-config"]).unwrap(), Cli {
+            command: Command::CheckConfig,
+            config: String::from("/etc/pistonproxy.yaml"),
+            listen: None
+        });
+        assert_eq!(parse(&["--listen=127.0.0.1:25566"]).unwrap().listen, Some("127.0.0.1:25566".parse().unwrap()));
+        assert_eq!(parse(&["-l", "25566"]).unwrap().listen, Some("0.0.0.0:25566".parse().unwrap()));
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--listen", "localhost"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["version", "run"]).is_err());
+    }
+    
+    #[test]
+    fn check_default_config() {
+        let config: Config = serde_yaml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(config.settings.listen, 25565);
+        assert!(config.fallback.is_some());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::packet::{MinecraftPacket, MinecraftProtocolState, PacketParseError};
-use crate::reader::{CursoredVarDataReader};
+use crate::reader::{CursoredVarDataReader, VarDataReader};
 use crate::writer::CursoredVarDataWriter;
 
 pub struct HandshakePacket {
@@ -38,4 +38,163 @@ impl From<HandshakePacket> for MinecraftPacket {
         
         packet
     }
-}
\ No newline at end of file
+}
+pub struct PingPacket {
+    pub payload: i64
+}
+
+impl TryFrom<&mut MinecraftPacket> for PingPacket {
+    type Error = PacketParseError;
+    
+    fn try_from(packet: &mut MinecraftPacket) -> Result<Self, Self::Error> {
+        CursoredVarDataReader::reset_cursor(packet);
+        let f1 = packet.read_i64().ok_or(PacketParseError::MalformedField(String::from("payload")))?;
+        Ok(PingPacket {
+            payload: f1
+        })
+    }
+}
+
+/// Only the player name is read, fields following it differ between protocol versions.
+pub struct LoginStartPacket {
+    pub name: String
+}
+
+impl TryFrom<&mut MinecraftPacket> for LoginStartPacket {
+    type Error = PacketParseError;
+    
+    fn try_from(packet: &mut MinecraftPacket) -> Result<Self, Self::Error> {
+        CursoredVarDataReader::reset_cursor(packet);
+        let f1 = packet.read_string().ok_or(PacketParseError::MalformedField(String::from("name")))?;
+        Ok(LoginStartPacket {
+            name: f1
+        })
+    }
+}
+
+/// Answer to a login plugin request, `data` is `None` when the channel is not understood.
+pub struct LoginPluginResponsePacket {
+    pub message_id: i32,
+    pub data: Option<Vec<u8>>
+}
+
+impl From<LoginPluginResponsePacket> for MinecraftPacket {
+    fn from(value: LoginPluginResponsePacket) -> Self {
+        let mut packet = MinecraftPacket::empty();
+        packet.id = 2;
+        packet.write_int(value.message_id);
+        packet.write_bool(value.data.is_some());
+        if let Some(data) = value.data {
+            packet.write_bytes(&data);
+        }
+        
+        packet
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LegacyPingVersion {
+    /// Beta 1.8 to 1.3, response contains only motd and player counts
+    Beta,
+    /// 1.4 and 1.5
+    V1_4,
+    /// 1.6, ping includes the hostname and port the client connected to
+    V1_6
+}
+
+pub struct LegacyPingPacket {
+    pub version: LegacyPingVersion,
+    pub protocol_version: Option<u8>,
+    pub server_address: Option<String>,
+    pub server_port: Option<u16>
+}
+
+impl TryFrom<&mut MinecraftPacket> for LegacyPingPacket {
+    type Error = PacketParseError;
+    
+    fn try_from(packet: &mut MinecraftPacket) -> Result<Self, Self::Error> {
+        let data = &packet.data;
+        if data.is_empty() {
+            return Ok(LegacyPingPacket {
+                version: LegacyPingVersion::Beta,
+                protocol_version: None,
+                server_address: None,
+                server_port: None
+            })
+        }
+        
+        if data.len() < 2 || data[1] != 0xFA {
+            return Ok(LegacyPingPacket {
+                version: LegacyPingVersion::V1_4,
+                protocol_version: None,
+                server_address: None,
+                server_port: None
+            })
+        }
+        
+        let (channel, len) = data.read_utf16_string(2).ok_or(PacketParseError::MalformedField(String::from("channel")))?;
+        if channel != "MC|PingHost" {
+            return Err(PacketParseError::MalformedField(String::from("channel")));
+        }
+        
+        // skip length of the plugin message data
+        let offset = 2 + len + 2;
+        let f1 = *data.get(offset).ok_or(PacketParseError::MalformedField(String::from("protocol_version")))?;
+        let (f2, len) = data.read_utf16_string(offset + 1).ok_or(PacketParseError::MalformedField(String::from("server_address")))?;
+        let port_offset = offset + 1 + len;
+        let f3 = data.get(port_offset..(port_offset + 4)).ok_or(PacketParseError::MalformedField(String::from("server_port")))?;
+        let f3 = i32::from_be_bytes([f3[0], f3[1], f3[2], f3[3]]);
+        Ok(LegacyPingPacket {
+            version: LegacyPingVersion::V1_6,
+            protocol_version: Some(f1),
+            server_address: Some(f2),
+            server_port: Some(f3 as u16)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::writer::VarDataWriter;
+    use super::*;
+    
+    fn create_legacy_ping(host: &str, port: i32) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0x4A];
+        let len = data.len();
+        data.write_utf16_string(host, len);
+        data.extend_from_slice(&port.to_be_bytes());
+        
+        let mut buf: Vec<u8> = vec![0xFE, 0x01, 0xFA];
+        let len = buf.len();
+        buf.write_utf16_string("MC|PingHost", len);
+        let len = buf.len();
+        buf.write_u16(data.len() as u16, len);
+        buf.extend_from_slice(&data);
+        buf
+    }
+    
+    #[test]
+    fn check_legacy_ping_versions() {
+        let (mut packet, len) = MinecraftPacket::parse_legacy_ping(vec![0xFE]).unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(LegacyPingPacket::try_from(&mut packet).unwrap().version, LegacyPingVersion::Beta);
+        
+        let (mut packet, len) = MinecraftPacket::parse_legacy_ping(vec![0xFE, 0x01]).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(LegacyPingPacket::try_from(&mut packet).unwrap().version, LegacyPingVersion::V1_4);
+    }
+    
+    #[test]
+    fn check_legacy_ping_host() {
+        let buf = create_legacy_ping("mc.example.net", 25565);
+        assert!(MinecraftPacket::parse_legacy_ping(buf[0..20].to_vec()).is_err());
+        
+        let (mut packet, len) = MinecraftPacket::parse_legacy_ping(buf.clone()).unwrap();
+        assert_eq!(len, buf.len());
+        let ping = LegacyPingPacket::try_from(&mut packet).unwrap();
+        assert_eq!(ping.version, LegacyPingVersion::V1_6);
+        assert_eq!(ping.protocol_version, Some(0x4A));
+        assert_eq!(ping.server_address, Some(String::from("mc.example.net")));
+        assert_eq!(ping.server_port, Some(25565));
+    }
+}
@@ -1,13 +1,30 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
-use std::sync::{Arc};
-use once_cell::sync::Lazy;
+use std::net::IpAddr;
+use std::mem::replace;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use once_cell::sync::OnceCell;
 use serde::Deserialize;
+use crate::cidr::Cidr;
+use crate::forwarding::ForwardingMode;
+use crate::geoip::GeoInfo;
+use crate::balancer::BalancingStrategy;
+use crate::hostname::{expand_template, normalize_hostname, parse_origin, HostnamePattern, HostnameRegex};
+use crate::proxy_protocol::ProxyProtocolVersion;
+use crate::validation::validate;
 
-pub const VERSION_PROXY_NAME: &'static str = "0.0.1-unstable";
-pub const VERSION_PROTOCOL_NAME: &'static str = "1.20.4";
+pub const VERSION_PROXY_NAME: &str = "0.0.1-unstable";
+pub const VERSION_PROTOCOL_NAME: &str = "1.20.4";
+pub const VERSION_PROTOCOL: u32 = 765;
 pub const BUFFER_SIZE: usize = 4096;
+pub const DEFAULT_DISCONNECT_MESSAGE: &str = "No further information";
+/// Interval in which background tasks disabled by the config look for a reloaded config enabling them.
+pub const IDLE_INTERVAL: Duration = Duration::from_secs(5);
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, PartialOrd, Clone, Debug, Deserialize)]
 pub enum LogLevel {
     NONE = 0,
@@ -16,54 +33,647 @@ pub enum LogLevel {
     DEBUG = 3
 }
 
+impl LogLevel {
+    /// Filter of the logger: only warnings, also the connections, also how they are handled, also the data they receive.
+    pub fn filter(&self) -> &'static str {
+        match self {
+            LogLevel::NONE => "warn",
+            LogLevel::CONNECTION => "info",
+            LogLevel::VERBOSE => "debug",
+            LogLevel::DEBUG => "trace"
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ConfigSettings {
     pub cache_size: usize,
+    /// Time in milliseconds for which a cached backend status is served.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+    /// Time in milliseconds in which a client must complete the handshake and the status or login start, must be positive.
     pub handshake_timeout: u32,
+    /// Minimum number of bytes per second a client must send until its connection is forwarded.
+    #[serde(default = "default_handshake_min_rate")]
+    pub handshake_min_rate: u32,
     pub client_buffer_size: usize,
     pub client_packets_limit: u32,
     pub backend_buffer_size: usize,
+    /// Time in milliseconds in which a client may open at most `ratelimit` connections, unlimited when zero.
     pub ratelimit_window: u32,
     pub ratelimit: u32,
+    /// Length of the network prefix IPv6 clients are rate limited by.
+    #[serde(default = "default_ratelimit_ipv6_prefix")]
+    pub ratelimit_ipv6_prefix: u8,
+    #[serde(default)]
+    pub ratelimit_action: RejectAction,
+    /// Kick message of rate limited clients.
+    pub ratelimit_message: Option<String>,
+    #[serde(default)]
+    pub blocklist_action: RejectAction,
+    /// Kick message of blocked clients.
+    pub blocklist_message: Option<String>,
+    /// Time in milliseconds for which a tarpitted connection is held open.
+    #[serde(default = "default_tarpit_time")]
+    pub tarpit_time: u64,
+    /// Maximum number of connections held in the tarpit, further tarpitted connections are dropped.
+    #[serde(default = "default_tarpit_limit")]
+    pub tarpit_limit: usize,
+    /// Maximum number of open connections of a single client address to each endpoint, unlimited when zero.
+    /// Connections which did not send a handshake yet are limited separately.
     pub concurrent_limit: u32,
+    /// Kick message of clients over the concurrent limit.
+    pub concurrent_limit_message: Option<String>,
+    /// Networks which are not limited in the number of connections, such as monitoring hosts.
+    #[serde(default)]
+    pub concurrent_limit_exempt: Vec<Cidr>,
     pub clients_limit: u32,
     pub listen: u16,
+    /// Messages which are logged, read at startup. A filter in the `RUST_LOG` variable takes precedence.
     pub log: LogLevel,
-    pub log_inspect_buffer_limit: usize
+    /// Active health checking of origins, all origins are considered healthy when not set.
+    pub health_check: Option<ConfigHealthCheck>,
+    /// Networks of load balancers which send a PROXY protocol header with the actual client address.
+    #[serde(default)]
+    pub proxy_protocol_trusted: Vec<Cidr>,
+    /// MaxMind databases (`.mmdb`) the country and ASN of clients are looked up in, such as GeoLite2-Country and GeoLite2-ASN.
+    /// They are loaded at startup, a reload of the config neither loads other databases nor updated files.
+    #[serde(default)]
+    pub geoip_databases: Vec<String>,
+    /// Time in milliseconds the sessions are given to drain on shutdown before they are disconnected.
+    /// Forwarded sessions encrypted by the origin, such as those of origins in online mode, are closed without the message.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Kick message of players and server list description while the proxy shuts down.
+    #[serde(default = "default_shutdown_message")]
+    pub shutdown_message: String,
+    /// Interval in milliseconds in which the config file is checked for changes, it is reloaded only on SIGHUP when zero.
+    #[serde(default)]
+    pub config_watch_interval: u64,
+    /// Name servers hostname origins are resolved by, as addresses with an optional port. Name servers
+    /// of `/etc/resolv.conf` are used when not set, the hosts file is not consulted.
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    /// Time in milliseconds in which a name server must answer before the next one is asked.
+    #[serde(default = "default_dns_timeout")]
+    pub dns_timeout: u64
+}
+
+fn default_cache_ttl() -> u64 {
+    5000
+}
+
+fn default_handshake_min_rate() -> u32 {
+    16
+}
+
+fn default_ratelimit_ipv6_prefix() -> u8 {
+    64
+}
+
+fn default_tarpit_time() -> u64 {
+    30000
+}
+
+fn default_tarpit_limit() -> usize {
+    1024
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30000
+}
+
+fn default_dns_timeout() -> u64 {
+    2000
+}
+
+fn default_shutdown_message() -> String {
+    String::from("Proxy is restarting, please reconnect in a moment")
+}
+
+/// Way a rejected connection is closed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectAction {
+    /// Closes the connection without any response
+    #[default]
+    Drop,
+    /// Disconnects the client with a message once it sends the handshake
+    Kick,
+    /// Holds the connection open without any response for `tarpit_time`
+    Tarpit
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigHealthCheck {
+    /// Time in milliseconds between two checks of an origin
+    #[serde(default = "default_health_check_interval")]
+    pub interval: u64,
+    /// Time in milliseconds in which the origin must answer a status ping
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout: u64,
+    /// Number of consecutive successful checks after which an origin is up
+    #[serde(default = "default_health_check_rise")]
+    pub rise: u32,
+    /// Number of consecutive failed checks after which an origin is down
+    #[serde(default = "default_health_check_fall")]
+    pub fall: u32
+}
+
+fn default_health_check_interval() -> u64 {
+    5000
+}
+
+fn default_health_check_timeout() -> u64 {
+    2000
+}
+
+fn default_health_check_rise() -> u32 {
+    2
+}
+
+fn default_health_check_fall() -> u32 {
+    3
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum ConfigOriginEntry {
+    Address(String),
+    Weighted {
+        address: String,
+        #[serde(default = "default_origin_weight")]
+        weight: u32
+    }
+}
+
+fn default_origin_weight() -> u32 {
+    1
+}
+
+/// Backend address with its load-balancing weight, written either as a plain address or as a mapping.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(from = "ConfigOriginEntry")]
+pub struct ConfigOrigin {
+    pub address: String,
+    pub weight: u32
+}
+
+impl From<ConfigOriginEntry> for ConfigOrigin {
+    fn from(value: ConfigOriginEntry) -> Self {
+        match value {
+            ConfigOriginEntry::Address(address) => ConfigOrigin { address, weight: default_origin_weight() },
+            ConfigOriginEntry::Weighted { address, weight } => ConfigOrigin { address, weight }
+        }
+    }
+}
+
+/// Replacement of a handshake field sent to the origin, `true` uses the value from the origin address.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ConfigRewrite<T> {
+    Origin(bool),
+    Value(T)
+}
+
+impl<T: Clone> ConfigRewrite<T> {
+    /// Value the field is replaced with, `None` keeps the value sent by the client.
+    pub fn value(&self, origin_value: Option<T>) -> Option<T> {
+        match self {
+            ConfigRewrite::Origin(true) => origin_value,
+            ConfigRewrite::Origin(false) => None,
+            ConfigRewrite::Value(value) => Some(value.clone())
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct ConfigEndpoint {
-    pub hostname: String,
+    /// Exact or wildcard hostname the endpoint is matched by, not used by the fallback endpoint
+    pub hostname: Option<HostnamePattern>,
+    /// Regular expression the endpoint is matched by when no exact or wildcard hostname matches
+    pub hostname_regex: Option<HostnameRegex>,
+    /// Address of the backend, may contain `{name}` placeholders captured from the hostname
     pub origin: Option<String>,
+    /// Backends the connections are balanced between, in addition to `origin`
+    #[serde(default)]
+    pub origins: Vec<ConfigOrigin>,
+    /// Backend of Forge clients, they are balanced between `origin` and `origins` as well when no forge origin is set
+    pub forge_origin: Option<String>,
+    #[serde(default)]
+    pub forge_origins: Vec<ConfigOrigin>,
+    #[serde(default)]
+    pub strategy: BalancingStrategy,
     pub motd: Option<String>,
-    pub message: Option<String>
+    pub message: Option<String>,
+    /// Closes connections without any response
+    #[serde(default)]
+    pub drop: bool,
+    /// Sends a PROXY protocol header with the client address to the origin
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    /// Maximum number of open connections of a single client address to this endpoint instead of the global
+    /// `concurrent_limit`, unlimited when zero
+    pub concurrent_limit: Option<u32>,
+    /// Forwards the player address and offline UUID to the origin during login
+    pub forwarding: Option<ForwardingMode>,
+    /// Secret shared with the origin which signs the player info of Velocity forwarding
+    pub forwarding_secret: Option<String>,
+    /// Acknowledges that forwarded players are not authenticated, required by `forwarding`
+    #[serde(default)]
+    pub allow_unauthenticated_forwarding: bool,
+    /// Hostname sent to the origin in the handshake instead of the one the client connected to
+    pub rewrite_host: Option<ConfigRewrite<String>>,
+    /// Port sent to the origin in the handshake instead of the one the client connected to
+    pub rewrite_port: Option<ConfigRewrite<u16>>,
+    /// Networks allowed to connect, clients outside of them are denied unless the list is empty
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    /// Countries (ISO 3166-1 alpha-2 codes) allowed to connect, looked up in `geoip_databases`
+    #[serde(default)]
+    pub allow_countries: Vec<String>,
+    /// Autonomous system numbers allowed to connect, looked up in `geoip_databases`
+    #[serde(default)]
+    pub allow_asns: Vec<u32>,
+    /// Networks denied to connect, takes precedence over `allow`
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+    #[serde(default)]
+    pub deny_countries: Vec<String>,
+    #[serde(default)]
+    pub deny_asns: Vec<u32>,
+    /// Disconnect message of denied clients, `message` is sent when not set
+    pub deny_message: Option<String>,
+    /// Whether the origins were replaced by the forge origins for a Forge client
+    #[serde(skip)]
+    pub forge: bool
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub settings: ConfigSettings,
     pub endpoints: Vec<ConfigEndpoint>,
-    pub blocklist: Vec<String>
+    /// Endpoint used for connections with an unknown hostname or without any hostname (legacy pings).
+    /// It forwards to `origin`, shows `motd` in the server list, kicks with `message` or drops the connection.
+    pub fallback: Option<ConfigEndpoint>,
+    /// Addresses, networks and hostnames whose connections are rejected by `blocklist_action`.
+    #[serde(default)]
+    pub blocklist: Vec<BlocklistEntry>
+}
+
+/// Entry of the blocklist, either an address or network (`203.0.113.0/24`) or a hostname pattern (`*.example.net`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BlocklistEntry {
+    Address(Cidr),
+    Hostname(HostnamePattern)
+}
+
+impl TryFrom<String> for BlocklistEntry {
+    type Error = String;
+    
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        // a mistyped address taken for a hostname would never match any client, so it is an error instead
+        let address_like = value.contains(['/', ':']) || value.chars().all(|c| c.is_ascii_digit() || c == '.');
+        match value.parse::<Cidr>() {
+            Ok(cidr) => Ok(BlocklistEntry::Address(cidr)),
+            Err(e) if address_like => Err(e),
+            Err(_) => HostnamePattern::try_from(value).map(BlocklistEntry::Hostname)
+        }
+    }
+}
+
+impl ConfigEndpoint {
+    fn with_captures(&self, captures: &HashMap<String, String>) -> ConfigEndpoint {
+        let mut endpoint = self.clone();
+        endpoint.origin = self.origin.as_ref().map(|origin| expand_template(origin, captures));
+        if let Some(ConfigRewrite::Value(host)) = &self.rewrite_host {
+            endpoint.rewrite_host = Some(ConfigRewrite::Value(expand_template(host, captures)));
+        }
+        endpoint.forge_origin = self.forge_origin.as_ref().map(|origin| expand_template(origin, captures));
+        for origin in endpoint.origins.iter_mut().chain(endpoint.forge_origins.iter_mut()) {
+            origin.address = expand_template(&origin.address, captures);
+        }
+        endpoint
+    }
+    
+    /// Placeholders captured by the hostname or regular expression, none for the fallback endpoint.
+    pub fn capture_names(&self) -> Vec<String> {
+        match (&self.hostname, &self.hostname_regex) {
+            (Some(hostname), _) => hostname.capture_names(),
+            (None, Some(regex)) => regex.capture_names(),
+            (None, None) => Vec::new()
+        }
+    }
+    
+    /// Hostname or regular expression the endpoint is matched by, `fallback` for the fallback endpoint.
+    pub fn name(&self) -> &str {
+        match (&self.hostname, &self.hostname_regex) {
+            (Some(hostname), _) => &hostname.pattern,
+            (None, Some(regex)) => &regex.pattern,
+            (None, None) => "fallback"
+        }
+    }
+    
+    /// All origins of the endpoint, `origin` is listed first with the default weight.
+    pub fn origins(&self) -> Vec<ConfigOrigin> {
+        let origin = self.origin.iter().map(|address| ConfigOrigin {
+            address: address.clone(),
+            weight: default_origin_weight()
+        });
+        origin.chain(self.origins.iter().cloned()).collect()
+    }
+    
+    /// Whether all origins are valid addresses, origins expanded from a regex capture may not be.
+    pub fn has_valid_origins(&self) -> bool {
+        self.origins().iter().all(|origin| parse_origin(&origin.address).is_ok())
+    }
+    
+    /// Whether the client is denied by the endpoint's deny and allow lists. Any deny list takes precedence,
+    /// otherwise a client matching any of the allow lists is allowed. The global blocklist is applied
+    /// before the endpoint is resolved, so these lists can only restrict access further.
+    pub fn is_denied(&self, ip: IpAddr, geo: &GeoInfo) -> bool {
+        let country_listed = |countries: &Vec<String>| geo.country.as_ref()
+            .is_some_and(|country| countries.iter().any(|listed| listed.eq_ignore_ascii_case(country)));
+        let asn_listed = |asns: &Vec<u32>| geo.asn.is_some_and(|asn| asns.contains(&asn));
+        
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) || country_listed(&self.deny_countries) || asn_listed(&self.deny_asns) {
+            return true
+        }
+        let restricted = !self.allow.is_empty() || !self.allow_countries.is_empty() || !self.allow_asns.is_empty();
+        let allowed = self.allow.iter().any(|cidr| cidr.contains(ip)) || country_listed(&self.allow_countries) || asn_listed(&self.allow_asns);
+        restricted && !allowed
+    }
+    
+    /// The endpoint used for Forge clients, with forge origins in place of the origins when it has any.
+    pub fn for_forge(&self) -> ConfigEndpoint {
+        let mut endpoint = self.clone();
+        if self.forge_origin.is_some() || !self.forge_origins.is_empty() {
+            endpoint.origin = self.forge_origin.clone();
+            endpoint.origins = self.forge_origins.clone();
+            endpoint.forge = true;
+        }
+        endpoint
+    }
 }
 
 impl Config {
-    pub fn find_endpoint(&self, addr: String) -> Option<&ConfigEndpoint> {
-        self.endpoints.iter().find(|ep| ep.hostname == addr)
+    /// Finds an endpoint for the hostname. Exact hostnames take precedence over wildcards, the wildcard
+    /// with the most literal characters is preferred and regular expressions are tried last in config order.
+    pub fn find_endpoint(&self, addr: String) -> Option<ConfigEndpoint> {
+        let addr = normalize_hostname(&addr);
+        
+        let exact = self.endpoints.iter()
+            .find(|ep| ep.hostname.as_ref().is_some_and(|hostname| !hostname.is_wildcard() && hostname.pattern == addr));
+        if let Some(endpoint) = exact {
+            return Some(endpoint.clone())
+        }
+        
+        let wildcard = self.endpoints.iter()
+            .filter_map(|ep| {
+                let hostname = ep.hostname.as_ref().filter(|hostname| hostname.is_wildcard())?;
+                hostname.captures(&addr).map(|captures| (ep, hostname.specificity(), captures))
+            })
+            .min_by_key(|(_, specificity, _)| Reverse(*specificity));
+        if let Some((endpoint, _, captures)) = wildcard {
+            return Some(endpoint.with_captures(&captures))
+        }
+        
+        self.endpoints.iter()
+            .find_map(|ep| {
+                let captures = ep.hostname_regex.as_ref()?.captures(&addr)?;
+                Some(ep.with_captures(&captures))
+            })
+    }
+    
+    /// Finds an endpoint for the hostname, connections with unknown hostname are routed to the fallback endpoint.
+    pub fn resolve_endpoint(&self, addr: Option<String>) -> Option<ConfigEndpoint> {
+        addr.and_then(|addr| self.find_endpoint(addr)).or(self.fallback.clone())
+    }
+    
+    pub fn is_address_blocked(&self, ip: IpAddr) -> bool {
+        self.blocklist.iter().any(|entry| matches!(entry, BlocklistEntry::Address(cidr) if cidr.contains(ip)))
+    }
+    
+    pub fn is_hostname_blocked(&self, hostname: &str) -> bool {
+        let hostname = normalize_hostname(hostname);
+        self.blocklist.iter().any(|entry| matches!(entry, BlocklistEntry::Hostname(pattern) if pattern.captures(&hostname).is_some()))
     }
 }
 
-static CONFIG: Lazy<Arc<Config>> = Lazy::new(|| {
-    Arc::new(load_config())
-});
+pub const DEFAULT_CONFIG_PATH: &str = "./config.yaml";
+
+static CONFIG_PATH: OnceCell<String> = OnceCell::new();
+
+static CONFIG: OnceCell<RwLock<Arc<Config>>> = OnceCell::new();
+
+/// Sets the path the config is loaded from, it must be set before the config is loaded.
+pub fn set_config_path(path: String) {
+    _ = CONFIG_PATH.set(path);
+}
 
-fn load_config() -> Config {
-    let file = File::open("./config.yaml").expect("Failed to load config.yaml. Does the file exist?");
+pub fn config_path() -> &'static str {
+    CONFIG_PATH.get().map(String::as_str).unwrap_or(DEFAULT_CONFIG_PATH)
+}
+
+/// Reads and validates the config, the error lists every problem found with its YAML path.
+pub fn read_config(path: &str) -> Result<Config, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to load {}: {}", path, e))?;
     let reader = BufReader::new(file);
-    let config: Config = serde_yaml::from_reader(reader).expect("Failed to read config.yaml");
-    config
+    let config: Config = serde_yaml::from_reader(reader).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    
+    let errors = validate(&config);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|error| format!("  {}", error)).collect();
+        return Err(format!("Invalid config {}:\n{}", path, errors.join("\n")))
+    }
+    Ok(config)
 }
 
+/// Loads the config used by the proxy, it must be loaded before any connection is accepted.
+pub fn load_config() -> Result<(), String> {
+    let config = read_config(config_path())?;
+    _ = CONFIG.set(RwLock::new(Arc::new(config)));
+    Ok(())
+}
+
+fn current_config() -> &'static RwLock<Arc<Config>> {
+    CONFIG.get().expect("config is not loaded")
+}
+
+/// Config of new connections, accepted connections keep using the config they got.
 pub fn get_config() -> Arc<Config> {
-    CONFIG.clone()
+    current_config().read().unwrap().clone()
+}
+
+/// Reads the config file again and replaces the current config if the whole file is valid,
+/// returns the replaced and the new config.
+pub fn reload_config() -> Result<(Arc<Config>, Arc<Config>), String> {
+    let config = Arc::new(read_config(config_path())?);
+    let previous = replace(&mut *current_config().write().unwrap(), config.clone());
+    Ok((previous, config))
+}
+
+#[cfg(test)]
+const TEST_SETTINGS: &str = r#"
+settings: { cache_size: 0, handshake_timeout: 5000, client_buffer_size: 4096, client_packets_limit: 8, backend_buffer_size: 4096,
+            ratelimit_window: 1000, ratelimit: 10, concurrent_limit: 4, clients_limit: 100, listen: 25565, log: NONE }
+endpoints: []
+"#;
+
+/// Config of tests with the minimal settings, top level keys of `extra` replace them except for
+/// `settings`, whose keys are merged into the minimal settings.
+#[cfg(test)]
+pub fn test_config(extra: &str) -> Config {
+    let mut config: serde_yaml::Mapping = serde_yaml::from_str(TEST_SETTINGS).unwrap();
+    let extra: serde_yaml::Mapping = serde_yaml::from_str(extra).unwrap();
+    for (key, value) in extra {
+        match (config.get_mut(&key), value) {
+            (Some(serde_yaml::Value::Mapping(settings)), serde_yaml::Value::Mapping(overrides)) if key == "settings" => settings.extend(overrides),
+            (_, value) => _ = config.insert(key, value)
+        }
+    }
+    serde_yaml::from_value(serde_yaml::Value::Mapping(config)).unwrap()
+}
+
+/// Makes the minimal config the config of new connections, for tests of code which reads it.
+#[cfg(test)]
+pub fn load_test_config() {
+    _ = CONFIG.get_or_init(|| RwLock::new(Arc::new(test_config(""))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_endpoint_precedence() {
+        let config = test_config(r#"
+endpoints:
+  - hostname_regex: '(?P<id>[0-9]+)\.node\.example\.net'
+    origin: "10.0.0.{id}:25565"
+  - hostname: "*.example.net"
+    origin: "10.0.1.{1}:25565"
+  - hostname: "*.eu.example.net"
+    origin: "10.0.2.{1}:25565"
+  - hostname: "lobby.eu.example.net"
+    origin: "10.0.3.1:25565"
+"#);
+        
+        let origin = |addr: &str| config.find_endpoint(addr.to_string()).and_then(|ep| ep.origin);
+        assert_eq!(origin("Lobby.EU.example.net."), Some(String::from("10.0.3.1:25565")));
+        assert_eq!(origin("survival.eu.example.net"), Some(String::from("10.0.2.survival:25565")));
+        assert_eq!(origin("12.example.net"), Some(String::from("10.0.1.12:25565")));
+        assert_eq!(origin("5.node.example.net"), Some(String::from("10.0.0.5:25565")));
+        assert_eq!(origin("a.b.example.net"), None);
+        assert_eq!(origin("example.org"), None);
+    }
+    
+    #[test]
+    fn check_fallback() {
+        let endpoints = r#"
+endpoints:
+  - hostname: play.example.net
+    origin: "10.0.0.1:25565"
+"#;
+        let config = test_config(endpoints);
+        assert!(config.resolve_endpoint(Some(String::from("example.org"))).is_none());
+        assert!(config.resolve_endpoint(None).is_none());
+        
+        let config = test_config(&format!("{}fallback: {{ motd: Unknown server, message: Unknown server }}", endpoints));
+        let name = |addr: Option<&str>| config.resolve_endpoint(addr.map(str::to_string)).map(|ep| ep.name().to_string());
+        assert_eq!(name(Some("Play.example.net")), Some(String::from("play.example.net")));
+        assert_eq!(name(Some("example.org")), Some(String::from("fallback")));
+        assert_eq!(name(None), Some(String::from("fallback")));
+    }
+    
+    #[test]
+    fn check_rewrite_options() {
+        let config = test_config(r#"
+endpoints:
+  - hostname: "*.example.net"
+    origin: "10.0.1.1:25566"
+    rewrite_host: "{1}.internal"
+    rewrite_port: true
+  - hostname: "lobby.example.org"
+    origin: "10.0.1.2:25566"
+    rewrite_host: false
+    rewrite_port: 25565
+"#);
+        
+        let endpoint = config.find_endpoint(String::from("survival.example.net")).unwrap();
+        assert_eq!(endpoint.rewrite_host.unwrap().value(None), Some(String::from("survival.internal")));
+        assert_eq!(endpoint.rewrite_port.unwrap().value(Some(25566)), Some(25566));
+        
+        let endpoint = config.find_endpoint(String::from("lobby.example.org")).unwrap();
+        assert_eq!(endpoint.rewrite_host.unwrap().value(Some(String::from("10.0.1.2"))), None);
+        assert_eq!(endpoint.rewrite_port.unwrap().value(Some(25566)), Some(25565));
+    }
+    
+    #[test]
+    fn check_blocklist() {
+        let config = test_config(r#"
+blocklist: ["203.0.113.0/24", "2001:db8::/32", "198.51.100.7", "*.bots.example.net", "spam.example.org"]
+"#);
+        
+        assert!(config.is_address_blocked("203.0.113.99".parse().unwrap()));
+        assert!(config.is_address_blocked("::ffff:198.51.100.7".parse().unwrap()));
+        assert!(config.is_address_blocked("2001:db8:5::1".parse().unwrap()));
+        assert!(!config.is_address_blocked("198.51.100.8".parse().unwrap()));
+        assert!(config.is_hostname_blocked("A.Bots.example.net"));
+        assert!(config.is_hostname_blocked("spam.example.org."));
+        assert!(!config.is_hostname_blocked("example.org"));
+        
+        for entry in ["10.0.0.0/33", "192.168.1.0/x", "10.0.0.300", "2001:db8::g"] {
+            assert!(BlocklistEntry::try_from(entry.to_string()).is_err(), "{} is not an address", entry);
+        }
+    }
+    
+    #[test]
+    fn check_allow_deny() {
+        let config = test_config(r#"
+endpoints:
+  - hostname: staff.example.net
+    origin: 10.0.0.1:25565
+    allow: ["10.8.0.0/16", "2001:db8::/32"]
+    deny: ["10.8.99.0/24"]
+  - hostname: play.example.net
+    origin: 10.0.0.2:25565
+    deny: ["198.51.100.7"]
+    deny_asns: [64500]
+  - hostname: eu.example.net
+    origin: 10.0.0.3:25565
+    allow: ["10.8.0.0/16"]
+    allow_countries: [cz, SK]
+"#);
+        
+        let unknown = GeoInfo::default();
+        let staff = config.find_endpoint("staff.example.net".to_string()).unwrap();
+        assert!(!staff.is_denied("10.8.1.2".parse().unwrap(), &unknown));
+        assert!(!staff.is_denied("::ffff:10.8.1.2".parse().unwrap(), &unknown));
+        assert!(!staff.is_denied("2001:db8::1".parse().unwrap(), &unknown));
+        assert!(staff.is_denied("10.8.99.1".parse().unwrap(), &unknown));
+        assert!(staff.is_denied("203.0.113.1".parse().unwrap(), &unknown));
+        
+        let play = config.find_endpoint("play.example.net".to_string()).unwrap();
+        assert!(play.is_denied("198.51.100.7".parse().unwrap(), &unknown));
+        assert!(!play.is_denied("198.51.100.8".parse().unwrap(), &unknown));
+        let hosting = GeoInfo {
+            country: Some(String::from("DE")),
+            asn: Some(64500),
+            organization: None
+        };
+        assert!(play.is_denied("198.51.100.8".parse().unwrap(), &hosting));
+        
+        let eu = config.find_endpoint("eu.example.net".to_string()).unwrap();
+        let client = GeoInfo {
+            country: Some(String::from("CZ")),
+            asn: Some(64496),
+            organization: None
+        };
+        assert!(!eu.is_denied("198.51.100.8".parse().unwrap(), &client));
+        assert!(eu.is_denied("198.51.100.8".parse().unwrap(), &unknown));
+        assert!(eu.is_denied("198.51.100.8".parse().unwrap(), &GeoInfo { country: Some(String::from("US")), ..client.clone() }));
+        // allowed network is not restricted by the allowed countries
+        assert!(!eu.is_denied("10.8.1.2".parse().unwrap(), &unknown));
+    }
 }
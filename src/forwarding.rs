@@ -0,0 +1,118 @@
+use std::net::IpAddr;
+use hmac::{Hmac, KeyInit, Mac};
+use log::warn;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use crate::config::Config;
+use crate::writer::VarDataWriter;
+
+/// Channel of the login plugin request by which the origin asks for the player info.
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+/// Forwarding version without the chat signing key of the player, accepted by all origins.
+const VELOCITY_FORWARDING_VERSION: i32 = 1;
+
+/// Way the origin learns the actual address and identity of the player.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardingMode {
+    /// Legacy BungeeCord forwarding, data is appended to the server address of the handshake
+    Bungeecord,
+    /// Velocity modern forwarding, data is signed with a shared secret and sent on request of the origin
+    Velocity
+}
+
+/// Warns about endpoints with forwarding. The origin trusts the forwarded name and UUID, but the proxy does not
+/// authenticate players, so anyone reaching such an origin through the proxy can log in as any player.
+pub fn warn_forwarding(config: &Config) {
+    for endpoint in config.endpoints.iter().chain(config.fallback.iter()) {
+        if let Some(mode) = endpoint.forwarding {
+            warn!(
+                "endpoint {} uses {:?} forwarding, its origins accept the player name and UUID the proxy does not verify",
+                endpoint.name(),
+                mode
+            );
+        }
+    }
+}
+
+/// UUID the player would get on a server running in offline mode, the proxy does not authenticate players.
+pub fn offline_uuid(name: &str) -> u128 {
+    let mut hash: [u8; 16] = Md5::digest(format!("OfflinePlayer:{}", name).as_bytes()).into();
+    // name based UUID, version 3
+    hash[6] = (hash[6] & 0x0F) | 0x30;
+    hash[8] = (hash[8] & 0x3F) | 0x80;
+    u128::from_be_bytes(hash)
+}
+
+/// Server address of a handshake forwarded in the BungeeCord format `host\0ip\0uuid\0properties`. Profile
+/// properties are appended only for Forge clients, whose marker is passed in properties the same way BungeeCord does.
+pub fn bungeecord_address(host: &str, client_ip: IpAddr, uuid: u128, fml_marker: Option<&str>) -> String {
+    let address = format!("{}\0{}\0{:032x}", host, client_ip.to_canonical(), uuid);
+    match fml_marker {
+        Some(marker) => {
+            // null characters of the marker would split the forwarded data
+            let properties = json!([
+                { "name": "forgeClient", "value": "true" },
+                { "name": "extraData", "value": marker.replace('\0', "\u{1}"), "signature": "" }
+            ]);
+            format!("{}\0{}", address, properties)
+        }
+        None => address
+    }
+}
+
+/// Response data to the `velocity:player_info` request, the payload is preceded by its HMAC-SHA256 signature.
+pub fn velocity_player_info(secret: &str, client_ip: IpAddr, uuid: u128, name: &str) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    let mut len = payload.write_int(VELOCITY_FORWARDING_VERSION, 0);
+    len += payload.write_string(&client_ip.to_canonical().to_string(), len);
+    payload.extend_from_slice(&uuid.to_be_bytes());
+    len += 16;
+    len += payload.write_string(name, len);
+    // no profile properties
+    payload.write_int(0, len);
+    
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&payload);
+    let mut data = mac.finalize().into_bytes().to_vec();
+    data.extend_from_slice(&payload);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_bungeecord_address() {
+        let uuid = offline_uuid("Notch");
+        assert_eq!(format!("{:032x}", uuid), "b50ad385829d3141a2167e7d7539ba7f");
+        
+        let address = bungeecord_address("mc.example.com", "::ffff:203.0.113.7".parse().unwrap(), uuid, None);
+        assert_eq!(address, "mc.example.com\x00203.0.113.7\x00b50ad385829d3141a2167e7d7539ba7f");
+        
+        let address = bungeecord_address("mc.example.com", "203.0.113.7".parse().unwrap(), uuid, Some("\0FML2\0"));
+        let properties = address.split('\0').nth(3).unwrap();
+        assert_eq!(properties, r#"[{"name":"forgeClient","value":"true"},{"name":"extraData","signature":"","value":"\u0001FML2\u0001"}]"#);
+    }
+    
+    #[test]
+    fn check_velocity_player_info() {
+        let uuid = offline_uuid("Notch");
+        let data = velocity_player_info("secret", "203.0.113.7".parse().unwrap(), uuid, "Notch");
+        let (signature, payload) = data.split_at(32);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(payload);
+        assert!(mac.verify_slice(signature).is_ok());
+        
+        let mut expected: Vec<u8> = vec![0x01, 0x0B];
+        expected.extend_from_slice(b"203.0.113.7");
+        expected.extend_from_slice(&uuid.to_be_bytes());
+        expected.push(0x05);
+        expected.extend_from_slice(b"Notch");
+        expected.push(0x00);
+        assert_eq!(payload, expected);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use log::info;
+use maxminddb::{geoip2, Reader};
+use once_cell::sync::OnceCell;
+
+/// Country and autonomous system of a client address, unknown when no database has a record of it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 country code
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub organization: Option<String>
+}
+
+impl Display for GeoInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "country={}", self.country.as_deref().unwrap_or("unknown"))?;
+        match self.asn {
+            Some(asn) => write!(f, ", asn=AS{} ({})", asn, self.organization.as_deref().unwrap_or("unknown")),
+            None => write!(f, ", asn=unknown")
+        }
+    }
+}
+
+/// Databases are read into memory once, lookups do not touch the disk.
+static DATABASES: OnceCell<Vec<Reader<Vec<u8>>>> = OnceCell::new();
+
+pub fn open_database(path: &str) -> Result<Reader<Vec<u8>>, String> {
+    Reader::open_readfile(path).map_err(|e| format!("Failed to load GeoIP database {}: {}", path, e))
+}
+
+/// Loads the configured databases, so a missing database fails the startup instead of the first connection.
+/// Databases are loaded only once, a reload of the config does not change them.
+pub fn load_databases(paths: &[String]) -> Result<(), String> {
+    let mut databases = Vec::new();
+    for path in paths {
+        let reader = open_database(path)?;
+        info!("loaded GeoIP database {} ({})", path, reader.metadata().database_type);
+        databases.push(reader);
+    }
+    _ = DATABASES.set(databases);
+    Ok(())
+}
+
+pub fn has_databases() -> bool {
+    DATABASES.get().is_some_and(|databases| !databases.is_empty())
+}
+
+/// Looks the address up in all databases, the first database with a record of the country or
+/// the autonomous system provides it. Country and ASN databases can be combined this way.
+pub fn lookup(ip: IpAddr) -> GeoInfo {
+    let ip = ip.to_canonical();
+    let mut info = GeoInfo::default();
+    for reader in DATABASES.get().into_iter().flatten() {
+        // IPv6 addresses can not be looked up in IPv4 databases
+        let Ok(result) = reader.lookup(ip) else {
+            continue
+        };
+        if info.country.is_none() {
+            if let Ok(Some(record)) = result.decode::<geoip2::Country>() {
+                info.country = record.country.iso_code.map(str::to_string);
+            }
+        }
+        if info.asn.is_none() {
+            if let Ok(Some(record)) = result.decode::<geoip2::Asn>() {
+                info.asn = record.autonomous_system_number;
+                info.organization = record.autonomous_system_organization.map(str::to_string);
+            }
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    
+    #[test]
+    fn check_load_error() {
+        let missing = std::env::temp_dir().join("pistonproxy-missing.mmdb");
+        let missing = missing.to_str().unwrap();
+        let error = open_database(missing).err().unwrap();
+        assert!(error.starts_with(&format!("Failed to load GeoIP database {}: ", missing)), "{}", error);
+        
+        let corrupt = std::env::temp_dir().join(format!("pistonproxy-corrupt-{}.mmdb", std::process::id()));
+        fs::write(&corrupt, b"not a maxmind database").unwrap();
+        let corrupt = corrupt.to_str().unwrap().to_string();
+        let result = load_databases(std::slice::from_ref(&corrupt));
+        _ = fs::remove_file(&corrupt);
+        let error = result.err().unwrap();
+        assert!(error.starts_with(&format!("Failed to load GeoIP database {}: ", corrupt)), "{}", error);
+        // the startup fails, no database is used for lookups
+        assert!(!has_databases());
+        assert_eq!(lookup("1.1.1.1".parse().unwrap()), GeoInfo::default());
+    }
+}
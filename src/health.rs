@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread::{scope, sleep, spawn};
+use std::time::Duration;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use crate::client_packets::HandshakePacket;
+use crate::config::{get_config, Config, ConfigHealthCheck, IDLE_INTERVAL, VERSION_PROTOCOL};
+use crate::packet::{MinecraftPacket, MinecraftProtocolState};
+use crate::proxy_protocol::{encode_local_header, ProxyProtocolVersion};
+use crate::hostname::split_host_port;
+use crate::resolver::DEFAULT_PORT;
+use crate::status::fetch_status;
+
+struct OriginHealth {
+    healthy: bool,
+    successes: u32,
+    failures: u32
+}
+
+static HEALTH: Lazy<Mutex<HashMap<String, OriginHealth>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// Origins which were not checked yet are considered healthy.
+pub fn is_healthy(origin: &str) -> bool {
+    HEALTH.lock().unwrap().get(origin).is_none_or(|health| health.healthy)
+}
+
+fn report(origin: &str, success: bool, options: &ConfigHealthCheck) {
+    let mut health = HEALTH.lock().unwrap();
+    let state = health.entry(origin.to_string()).or_insert(OriginHealth {
+        healthy: true,
+        successes: 0,
+        failures: 0
+    });
+    
+    if success {
+        state.successes += 1;
+        state.failures = 0;
+        if !state.healthy && state.successes >= options.rise {
+            info!("origin {} is up", origin);
+            state.healthy = true;
+        }
+    } else {
+        state.failures += 1;
+        state.successes = 0;
+        if state.healthy && state.failures >= options.fall {
+            warn!("origin {} is down", origin);
+            state.healthy = false;
+        }
+    }
+}
+
+/// Origins of all endpoints with the PROXY protocol version they expect. Templated origins
+/// depend on the hostname and can not be checked.
+fn checked_origins(config: &Config) -> Vec<(String, Option<ProxyProtocolVersion>)> {
+    let mut origins: Vec<(String, Option<ProxyProtocolVersion>)> = Vec::new();
+    for ep in config.endpoints.iter().chain(config.fallback.iter()) {
+        for origin in ep.origins().into_iter().chain(ep.for_forge().origins()) {
+            if !origin.address.contains('{') && !origins.iter().any(|(address, _)| *address == origin.address) {
+                origins.push((origin.address, ep.proxy_protocol));
+            }
+        }
+    }
+    origins
+}
+
+fn check_origin(origin: &str, proxy_protocol: Option<ProxyProtocolVersion>, options: &ConfigHealthCheck) -> bool {
+    let (host, port) = split_host_port(origin);
+    let handshake_packet = HandshakePacket {
+        protocol_version: VERSION_PROTOCOL,
+        server_address: host,
+        server_port: port.unwrap_or(DEFAULT_PORT),
+        next_state: MinecraftProtocolState::STATUS
+    };
+    let mut request = proxy_protocol.map(encode_local_header).unwrap_or_default();
+    request.extend_from_slice(&MinecraftPacket::from(handshake_packet).serialize());
+    match fetch_status(origin, &request, Duration::from_millis(options.timeout)) {
+        // the response is not cached, clients may get a different status for their hostname
+        Ok(_) => true,
+        Err(e) => {
+            debug!("health check of {} failed: {}", origin, e);
+            false
+        }
+    }
+}
+
+/// Forgets the health of origins which are no longer in the config, an origin added again starts as healthy.
+pub fn prune_health(config: &Config) {
+    let origins = checked_origins(config);
+    HEALTH.lock().unwrap().retain(|origin, _| origins.iter().any(|(address, _)| address == origin));
+}
+
+/// Periodically pings all origins and marks them up or down after `rise` successful or `fall` failed checks.
+pub fn spawn_health_checker() {
+    spawn(|| loop {
+        let config = get_config();
+        let options = match &config.settings.health_check {
+            Some(options) => options.clone(),
+            None => {
+                sleep(IDLE_INTERVAL);
+                continue
+            }
+        };
+        
+        let origins = checked_origins(&config);
+        prune_health(&config);
+        
+        scope(|s| {
+            for (origin, proxy_protocol) in origins.iter() {
+                let options = &options;
+                s.spawn(move || {
+                    let success = check_origin(origin, *proxy_protocol, options);
+                    report(origin, success, options);
+                });
+            }
+        });
+        
+        sleep(Duration::from_millis(options.interval));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_rise_fall() {
+        let options = ConfigHealthCheck {
+            interval: 1000,
+            timeout: 1000,
+            rise: 2,
+            fall: 3
+        };
+        let origin = "health-test:25565";
+        assert!(is_healthy(origin));
+        
+        report(origin, false, &options);
+        report(origin, false, &options);
+        assert!(is_healthy(origin));
+        report(origin, false, &options);
+        assert!(!is_healthy(origin));
+        
+        report(origin, true, &options);
+        assert!(!is_healthy(origin));
+        report(origin, true, &options);
+        assert!(is_healthy(origin));
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Single DNS label captured by a wildcard.
+const LABEL_EXPR: &str = "[A-Za-z0-9-]{1,63}";
+
+/// Hostname an endpoint is matched by. A pattern containing `*` or `{name}` segments is a wildcard,
+/// each of them matches a single DNS label and can be referenced by `{1}`, `{2}`, ... or by its name.
+/// Captured labels are put into origin addresses, so they may contain only letters, digits and hyphens.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HostnamePattern {
+    pub pattern: String,
+    wildcard: Option<Regex>,
+    specificity: usize
+}
+
+impl HostnamePattern {
+    pub fn is_wildcard(&self) -> bool {
+        self.wildcard.is_some()
+    }
+    
+    /// Number of literal characters, wildcards with more literal characters take precedence.
+    pub fn specificity(&self) -> usize {
+        self.specificity
+    }
+    
+    /// Placeholders which can be used in origins, the indexes and names of the wildcards.
+    pub fn capture_names(&self) -> Vec<String> {
+        self.wildcard.as_ref().map(capture_names).unwrap_or_default()
+    }
+    
+    pub fn captures(&self, hostname: &str) -> Option<HashMap<String, String>> {
+        match &self.wildcard {
+            Some(regex) => regex_captures(regex, hostname),
+            None if self.pattern == hostname => Some(HashMap::new()),
+            None => None
+        }
+    }
+}
+
+impl PartialEq for HostnamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl TryFrom<String> for HostnamePattern {
+    type Error = String;
+    
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let pattern = normalize_hostname(&value);
+        if !pattern.contains('*') && !pattern.contains('{') {
+            return Ok(HostnamePattern {
+                specificity: pattern.len(),
+                pattern,
+                wildcard: None
+            })
+        }
+        
+        let mut expr = String::from("^");
+        let mut specificity = 0;
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => expr.push_str(&format!("({})", LABEL_EXPR)),
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        return Err(format!("invalid placeholder {{{}}} in hostname {}", name, value))
+                    }
+                    expr.push_str(&format!("(?P<{}>{})", name, LABEL_EXPR));
+                }
+                c => {
+                    specificity += 1;
+                    expr.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+        }
+        expr.push('$');
+        
+        let wildcard = Regex::new(&expr).map_err(|e| format!("invalid hostname {}: {}", value, e))?;
+        Ok(HostnamePattern {
+            pattern,
+            wildcard: Some(wildcard),
+            specificity
+        })
+    }
+}
+
+/// Regular expression matched against the whole hostname, groups can be referenced by index or by name.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HostnameRegex {
+    pub pattern: String,
+    regex: Regex
+}
+
+impl HostnameRegex {
+    /// Placeholders which can be used in origins, the indexes and names of the groups.
+    pub fn capture_names(&self) -> Vec<String> {
+        capture_names(&self.regex)
+    }
+    
+    pub fn captures(&self, hostname: &str) -> Option<HashMap<String, String>> {
+        regex_captures(&self.regex, hostname)
+    }
+}
+
+impl PartialEq for HostnameRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl TryFrom<String> for HostnameRegex {
+    type Error = String;
+    
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let regex = Regex::new(&format!("^(?i:{})$", value)).map_err(|e| format!("invalid hostname regex {}: {}", value, e))?;
+        Ok(HostnameRegex {
+            pattern: value,
+            regex
+        })
+    }
+}
+
+fn capture_names(regex: &Regex) -> Vec<String> {
+    let mut names = Vec::new();
+    for (i, name) in regex.capture_names().enumerate() {
+        names.push(i.to_string());
+        names.extend(name.map(str::to_string));
+    }
+    names
+}
+
+fn regex_captures(regex: &Regex, hostname: &str) -> Option<HashMap<String, String>> {
+    let captures = regex.captures(hostname)?;
+    let mut values = HashMap::new();
+    for (i, name) in regex.capture_names().enumerate() {
+        if let Some(value) = captures.get(i) {
+            values.insert(i.to_string(), value.as_str().to_string());
+            if let Some(name) = name {
+                values.insert(name.to_string(), value.as_str().to_string());
+            }
+        }
+    }
+    Some(values)
+}
+
+/// Hostnames are case-insensitive and may be sent as a fully qualified name with a trailing dot.
+pub fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Replaces `{name}` placeholders with captured values, unknown placeholders are kept as they are.
+pub fn expand_template(template: &str, captures: &HashMap<String, String>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let name = &rest[(start + 1)..(start + end)];
+                match captures.get(name) {
+                    Some(value) => result.push_str(value),
+                    None => result.push_str(&rest[start..=(start + end)])
+                }
+                rest = &rest[(start + end + 1)..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Markers appended to the server address by Forge clients, `FML` up to 1.12, `FML2` up to 1.17 and `FML3` since 1.18.
+const FML_MARKERS: [&str; 3] = ["\0FML\0", "\0FML2\0", "\0FML3\0"];
+
+/// Splits the server address of a handshake into the hostname and the Forge marker. Any other data
+/// following a null character is not a part of the hostname and is dropped.
+pub fn split_fml_marker(address: &str) -> (&str, Option<&'static str>) {
+    match address.find('\0') {
+        Some(start) => {
+            let marker = FML_MARKERS.iter().find(|marker| &address[start..] == **marker).copied();
+            (&address[..start], marker)
+        }
+        None => (address, None)
+    }
+}
+
+/// Splits an origin address into its host and port, brackets of an IPv6 host are removed.
+pub fn split_host_port(address: &str) -> (String, Option<u16>) {
+    let (host, port) = match address.rsplit_once(':') {
+        // a colon inside of an IPv6 address without brackets is not a port separator
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => (host, port.parse().ok()),
+        _ => (address, None)
+    };
+    (host.trim_start_matches('[').trim_end_matches(']').to_string(), port)
+}
+
+/// Parses an origin address into its host and port. The host must be an IP address or a hostname,
+/// a written port must be valid.
+pub fn parse_origin(address: &str) -> Result<(String, Option<u16>), String> {
+    let (host, port) = split_host_port(address);
+    // anything cut off by the split is a port, which must be valid
+    let port_written = host.len() < address.trim_start_matches('[').trim_end_matches(']').len();
+    if host.is_empty() {
+        return Err(format!("origin {} has no host", address))
+    }
+    if port_written && port.is_none() {
+        return Err(format!("origin {} has an invalid port", address))
+    }
+    if host.parse::<IpAddr>().is_err() && !is_valid_hostname(&host) {
+        return Err(format!("origin {} has an invalid host", address))
+    }
+    Ok((host, port))
+}
+
+/// Whether the pattern is a valid hostname once its wildcards and placeholders are replaced by labels.
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let mut sample = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => sample.push('a'),
+            '{' => {
+                chars.by_ref().take_while(|c| *c != '}').for_each(drop);
+                sample.push('a');
+            }
+            c => sample.push(c)
+        }
+    }
+    is_valid_hostname(&sample)
+}
+
+/// Hostname of letters, digits, hyphens and underscores (used by container names) in labels of up to 63 characters.
+fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.len() <= 253 && hostname.trim_end_matches('.').split('.').all(|label| {
+        (1..=63).contains(&label.len()) && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_wildcard_captures() {
+        let pattern = HostnamePattern::try_from(String::from("*.Example.net")).unwrap();
+        assert!(pattern.is_wildcard());
+        assert_eq!(pattern.captures("example.net"), None);
+        assert_eq!(pattern.captures("a.b.example.net"), None);
+        let captures = pattern.captures("lobby.example.net").unwrap();
+        assert_eq!(captures.get("1"), Some(&String::from("lobby")));
+        // labels which would change the origin address are not captured
+        assert_eq!(pattern.captures("lobby:25566.example.net"), None);
+        assert_eq!(pattern.captures("a@b.example.net"), None);
+        assert_eq!(pattern.captures(&format!("{}.example.net", "a".repeat(64))), None);
+        
+        let pattern = HostnamePattern::try_from(String::from("{sub}.play.example.net")).unwrap();
+        let captures = pattern.captures("eu1.play.example.net").unwrap();
+        assert_eq!(expand_template("{sub}.internal:25565", &captures), "eu1.internal:25565");
+        assert_eq!(expand_template("{missing}.internal", &captures), "{missing}.internal");
+    }
+    
+    #[test]
+    fn check_regex_captures() {
+        let regex = HostnameRegex::try_from(String::from(r"(?P<id>[0-9]+)\.servers\.example\.net")).unwrap();
+        assert!(regex.captures("abc.servers.example.net").is_none());
+        assert!(regex.captures("1.servers.example.net.evil.com").is_none());
+        let captures = regex.captures("42.servers.example.net").unwrap();
+        assert_eq!(expand_template("10.0.0.{id}:{1}", &captures), "10.0.0.42:42");
+    }
+    
+    #[test]
+    fn check_fml_marker() {
+        assert_eq!(split_fml_marker("mc.example.net"), ("mc.example.net", None));
+        assert_eq!(split_fml_marker("mc.example.net\0FML2\0"), ("mc.example.net", Some("\0FML2\0")));
+        assert_eq!(split_fml_marker("mc.example.net\0FML3\0"), ("mc.example.net", Some("\0FML3\0")));
+        assert_eq!(split_fml_marker("mc.example.net\x00203.0.113.7\0uuid"), ("mc.example.net", None));
+    }
+    
+    #[test]
+    fn check_split_host_port() {
+        assert_eq!(split_host_port("10.0.0.1:25566"), (String::from("10.0.0.1"), Some(25566)));
+        assert_eq!(split_host_port("[2001:db8::1]:25565"), (String::from("2001:db8::1"), Some(25565)));
+        assert_eq!(split_host_port("2001:db8::1"), (String::from("2001:db8::1"), None));
+        assert_eq!(split_host_port("mc.internal"), (String::from("mc.internal"), None));
+    }
+    
+    #[test]
+    fn check_parse_origin() {
+        assert_eq!(parse_origin("mc_lobby:25566"), Ok((String::from("mc_lobby"), Some(25566))));
+        assert_eq!(parse_origin("[2001:db8::1]"), Ok((String::from("2001:db8::1"), None)));
+        assert!(parse_origin("10.0.0.1:99999").is_err());
+        assert!(parse_origin(":25565").is_err());
+        assert!(parse_origin("evil.example.net/x:25565").is_err());
+        assert!(parse_origin("a..example.net").is_err());
+    }
+    
+    #[test]
+    fn check_valid_pattern() {
+        assert!(is_valid_pattern("*.bots.example.net"));
+        assert!(is_valid_pattern("{sub}-eu.example.net."));
+        assert!(!is_valid_pattern("spam example.org"));
+        assert!(!is_valid_pattern("*.bots,example.net"));
+    }
+}
@@ -1,12 +1,24 @@
-use std::net::{Shutdown, TcpListener};
+use std::env::args;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
+use std::process::exit;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::thread::{spawn};
-use std::time::SystemTime;
+use std::thread::{park, sleep, spawn};
+use std::time::{Duration, SystemTime};
 use env_logger::Env;
-use log::{debug, info};
-use crate::config::{get_config, BUFFER_SIZE, VERSION_PROTOCOL_NAME, VERSION_PROXY_NAME};
-use crate::proxy::{ProxySocketInfo, ProxySocketState};
+use log::{debug, info, warn};
+use crate::cli::{version_text, Cli, Command, DEFAULT_CONFIG, USAGE};
+use crate::config::{config_path, get_config, Config, load_config, read_config, set_config_path, RejectAction, VERSION_PROTOCOL_NAME, VERSION_PROXY_NAME};
+use crate::forwarding::warn_forwarding;
+use crate::geoip::{has_databases, load_databases, lookup};
+use crate::health::spawn_health_checker;
+use crate::proxy::ProxySocketInfo;
+use crate::proxy_protocol::{accept_header, is_trusted};
+use crate::ratelimit::{check_rate_limit, ClientConnection};
+use crate::reload::spawn_config_reloader;
+use crate::shutdown::{is_shutting_down, spawn_signal_handler, Session};
+use crate::tarpit::{spawn_tarpit, tarpit};
+use crate::validation::{find_warnings, log_warnings};
 
 mod config;
 mod packet;
@@ -16,53 +28,211 @@ mod writer;
 mod server_packets;
 mod client_packets;
 mod chat;
+mod cidr;
+mod forwarding;
+mod balancer;
+mod health;
+mod proxy_protocol;
+mod hostname;
+mod status;
+mod ratelimit;
+mod geoip;
+mod shutdown;
+mod reload;
+mod cli;
+mod validation;
+mod resolver;
+mod tarpit;
+mod phase;
+
+/// Errors of `accept` when the process or the system is out of file descriptors.
+const ENFILE: i32 = 23;
+const EMFILE: i32 = 24;
+/// Time the listener waits after running out of file descriptors, so it does not spin on the pending connection.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 fn main() {
+    let cli = match Cli::parse(args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2)
+        }
+    };
+    set_config_path(cli.config);
+    
+    match cli.command {
+        Command::Run => run(cli.listen),
+        Command::CheckConfig => match read_config(config_path()) {
+            Ok(config) => {
+                for warning in find_warnings(&config) {
+                    eprintln!("warning: {}", warning);
+                }
+                println!("{} is valid", config_path())
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1)
+            }
+        },
+        Command::PrintDefaultConfig => print!("{}", DEFAULT_CONFIG),
+        Command::Version => println!("{}", version_text()),
+        Command::Help => println!("{}", USAGE)
+    }
+}
+
+fn run(listen: Option<SocketAddr>) {
     let start_time = SystemTime::now();
+    if let Err(e) = load_config() {
+        eprintln!("{}", e);
+        exit(1)
+    }
     let config = get_config();
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    env_logger::Builder::from_env(Env::default().default_filter_or(config.settings.log.filter())).init();
     
     info!("pistonproxy version {}, protocol version {}", VERSION_PROXY_NAME, VERSION_PROTOCOL_NAME);
     
-    let addr = format!("0.0.0.0:{}", config.settings.listen);
-    let listener = TcpListener::bind(addr.clone()).unwrap();
+    warn_forwarding(&config);
+    log_warnings(&config);
+    
+    let addr = listen.unwrap_or(SocketAddr::from(([0, 0, 0, 0], config.settings.listen)));
+    if let Err(e) = load_databases(&config.settings.geoip_databases) {
+        eprintln!("{}", e);
+        exit(1)
+    }
+    let listener = TcpListener::bind(addr).unwrap();
     let connections = Arc::new(AtomicU32::new(0));
+    spawn_health_checker();
+    spawn_tarpit();
+    spawn_signal_handler(listener.local_addr().unwrap());
+    spawn_config_reloader();
     
     info!("listening on {addr}");
     let startup_duration = start_time.elapsed().unwrap().as_micros();
     debug!("server is ready in {:.2} ms", (startup_duration as f32) / 1000.0);
     
     loop {
-        let (stream, addr) = listener.accept().unwrap();
-        debug!("[{}] accepted new connection", addr);
+        let (stream, addr) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                // running out of file descriptors does not resolve before some connections are closed
+                if matches!(e.raw_os_error(), Some(ENFILE | EMFILE)) {
+                    sleep(ACCEPT_BACKOFF);
+                }
+                continue
+            }
+        };
+        if is_shutting_down() {
+            // the signal handler wakes the listener, new connections are refused from now on
+            break
+        }
+        info!("[{}] accepted new connection", addr);
+        let config = get_config();
+        // connections relayed by a load balancer are checked once the PROXY header tells the client address
+        let relayed = is_trusted(addr.ip(), &config.settings.proxy_protocol_trusted);
+        let rejection = if relayed { None } else { check_address(addr, &config) };
+        match rejection {
+            Some((RejectAction::Drop, _)) => {
+                _ = stream.shutdown(Shutdown::Both);
+                continue
+            }
+            Some((RejectAction::Tarpit, _)) => {
+                tarpit(stream, addr, &config.settings);
+                continue
+            }
+            _ => {}
+        }
+        
         if connections.load(Ordering::Relaxed) < config.settings.clients_limit {
             connections.fetch_add(1, Ordering::SeqCst);
             let connections_close = connections.clone();
-            let addr_copy = addr.clone();
             spawn(move || {
-                let stream_copy = stream.try_clone().unwrap();
-                let socket_info_main: Arc<Mutex<ProxySocketInfo>> = Arc::new(Mutex::new(ProxySocketInfo {
-                    state: ProxySocketState::Handshake,
-                    last_activity: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis(),
-                    
-                    client_addr: addr_copy,
-                    client_socket: Some(stream_copy),
-                    client_send_buffer: vec![0; BUFFER_SIZE],
-                    client_send_buffer_len: 0,
-                    
-                    backend_addr: None,
-                    backend_socket: None,
-                    backend_send_buffer: vec![0; BUFFER_SIZE],
-                    backend_send_buffer_len: 0,
-                }));
-                
-                ProxySocketInfo::handle_client_connection(stream, addr_copy, socket_info_main);
-                debug!("[{}] socket closed", addr);
+                handle_connection(stream, addr, relayed, rejection);
                 connections_close.fetch_sub(1, Ordering::SeqCst);
             });
         } else {
             debug!("clients_limit exceeded");
-            stream.shutdown(Shutdown::Both).expect("stream close failed")
+            // the client may have reset the connection already
+            _ = stream.shutdown(Shutdown::Both);
         }
     }
+    
+    drop(listener);
+    info!("stopped accepting connections");
+    // the signal handler exits once the sessions drain
+    loop {
+        park();
+    }
+}
+
+/// Applies the blocklist and the rate limit to the client address, returns how the connection is rejected.
+fn check_address(addr: SocketAddr, config: &Config) -> Option<(RejectAction, Option<String>)> {
+    let settings = &config.settings;
+    if config.is_address_blocked(addr.ip()) {
+        debug!("[{}] address is blocked", addr);
+        Some((settings.blocklist_action, settings.blocklist_message.clone()))
+    } else if check_rate_limit(addr.ip(), settings) {
+        debug!("[{}] ratelimit exceeded", addr);
+        Some((settings.ratelimit_action, settings.ratelimit_message.clone()))
+    } else {
+        None
+    }
+}
+
+/// Resolves the actual client address of a connection relayed by a load balancer and checks it, then proxies the connection.
+fn handle_connection(mut stream: TcpStream, addr: SocketAddr, relayed: bool, rejection: Option<(RejectAction, Option<String>)>) {
+    let config = get_config();
+    let settings = &config.settings;
+    let timeout = Duration::from_millis(settings.handshake_timeout as u64);
+    let header = match accept_header(&mut stream, &settings.proxy_protocol_trusted, timeout) {
+        Ok(header) => header,
+        Err(e) => {
+            debug!("[{}] rejected connection: {}", addr, e);
+            _ = stream.shutdown(Shutdown::Both);
+            return
+        }
+    };
+    if header.source != addr {
+        debug!("[{}] connection relayed by {}", header.source, addr);
+    }
+    let addr = header.source;
+    
+    let rejection = if relayed { check_address(addr, &config) } else { rejection };
+    match rejection {
+        Some((RejectAction::Drop, _)) => {
+            _ = stream.shutdown(Shutdown::Both);
+            return
+        }
+        Some((RejectAction::Tarpit, _)) => {
+            tarpit(stream, addr, settings);
+            return
+        }
+        _ => {}
+    }
+    
+    // connections which never send a handshake count as well, the endpoint's limit applies once it is known
+    let connection = ClientConnection::open(addr.ip(), settings);
+    let rejection = match rejection {
+        None if connection.exceeds_limit(settings.concurrent_limit, settings) => {
+            debug!("[{}] concurrent_limit exceeded", addr);
+            Some((RejectAction::Kick, settings.concurrent_limit_message.clone()))
+        }
+        rejection => rejection
+    };
+    let stream_copy = stream.try_clone().unwrap();
+    let mut socket_info = ProxySocketInfo::new(addr, header.destination, stream_copy);
+    socket_info.connection = Some(connection);
+    if has_databases() {
+        socket_info.geo = lookup(addr.ip());
+        debug!("[{}] {}", addr, socket_info.geo);
+    }
+    if let Some((action, message)) = rejection {
+        socket_info.apply_reject_action(action, message, &config);
+    }
+    let socket_info_main: Arc<Mutex<ProxySocketInfo>> = Arc::new(Mutex::new(socket_info));
+    let _session = Session::register(&socket_info_main);
+    
+    ProxySocketInfo::handle_client_connection(stream, addr, socket_info_main, config);
+    info!("[{}] socket closed", addr);
 }
@@ -4,6 +4,8 @@ use crate::writer::{CursoredVarDataWriter, VarDataWriter};
 
 pub const SEGMENT_BITS: u8 = 0x7F;
 pub const CONTINUE_BIT: u8 = 0x80;
+pub const LEGACY_PING_ID: i32 = 255;
+pub const MAX_VARINT_LENGTH: usize = 5;
 
 pub struct MinecraftPacket {
     pub len: i32,
@@ -19,7 +21,8 @@ pub enum PacketParseError {
     EmptyBuffer
 }
 
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MinecraftProtocolState {
     HANDSHAKING,
     STATUS,
@@ -40,9 +43,9 @@ impl From<u16> for MinecraftProtocolState {
     }
 }
 
-impl Into<u16> for MinecraftProtocolState {
-    fn into(self) -> u16 {
-        match self {
+impl From<MinecraftProtocolState> for u16 {
+    fn from(val: MinecraftProtocolState) -> u16 {
+        match val {
             MinecraftProtocolState::HANDSHAKING => 0,
             MinecraftProtocolState::STATUS => 1,
             MinecraftProtocolState::LOGIN => 2,
@@ -63,50 +66,98 @@ impl MinecraftPacket {
     }
     
     pub fn parse_packet(buf: Vec<u8>) -> Result<(MinecraftPacket, usize), PacketParseError> {
-        if buf.len() == 0 {
+        MinecraftPacket::parse_packet_limited(buf, i32::MAX as usize)
+    }
+    
+    /// Parses a packet, declared lengths over `max_length` are rejected before the whole packet is received.
+    pub fn parse_packet_limited(buf: Vec<u8>, max_length: usize) -> Result<(MinecraftPacket, usize), PacketParseError> {
+        if buf.is_empty() {
             return Err(PacketParseError::EmptyBuffer);
         }
         
-        if buf.len() == 2 && buf[0] == 0xFE && buf[1] == 0x01 {
-            return Ok((MinecraftPacket {
-                len: 0,
-                id: 255,
-                cursor: 0,
-                data: Vec::new()
-            }, 2))
-        }
-        
-        let mut offset = 0;
-        if let Some((packet_length, len)) = buf.read_int(0) {
-            offset = offset + len;
-            if let Some((packet_id, len)) = buf.read_int(len) {
-                offset = offset + len;
-                let data_length = offset + packet_length as usize;
-                
-                assert_ne!(data_length, 0);
-                
-                if buf.len() >= data_length {
-                    let data = buf[offset..data_length].to_vec();
-                    Ok((MinecraftPacket {
-                        len: packet_length,
-                        id: packet_id,
-                        cursor: 0,
-                        data
-                    }, data_length))
-                } else {
-                    Err(PacketParseError::LengthMismatch)
-                }
+        if let Some((packet_length, prefix_len)) = buf.read_int(0) {
+            if packet_length <= 0 || packet_length as usize > max_length {
+                return Err(PacketParseError::MalformedField(String::from("length")));
+            }
+            
+            let data_length = prefix_len + packet_length as usize;
+            if buf.len() < data_length {
+                return Err(PacketParseError::LengthMismatch);
+            }
+            
+            // packet id is a part of the declared length, so it must be read within the frame
+            let frame = buf[0..data_length].to_vec();
+            if let Some((packet_id, len)) = frame.read_int(prefix_len) {
+                let data = frame[(prefix_len + len)..].to_vec();
+                Ok((MinecraftPacket {
+                    len: packet_length,
+                    id: packet_id,
+                    cursor: 0,
+                    data
+                }, data_length))
             } else {
-                Err(PacketParseError::LengthMismatch)
+                Err(PacketParseError::MalformedField(String::from("id")))
             }
+        } else if buf.len() >= MAX_VARINT_LENGTH {
+            // length prefix did not end within the longest possible VarInt
+            Err(PacketParseError::MalformedField(String::from("length")))
         } else {
             Err(PacketParseError::LengthMismatch)
         }
     }
     
-    pub fn create_disconnect_packet(msg: &String) -> MinecraftPacket {
+    /// Parses a pre-1.7 server list ping starting with `0xFE`, the returned packet holds the data following it.
+    /// Beta clients send only `FE`, 1.4 and 1.5 send `FE 01` and 1.6 clients follow it with a `MC|PingHost` plugin message.
+    pub fn parse_legacy_ping(buf: Vec<u8>) -> Result<(MinecraftPacket, usize), PacketParseError> {
+        if buf.is_empty() {
+            return Err(PacketParseError::EmptyBuffer);
+        }
+        
+        if buf[0] != 0xFE {
+            return Err(PacketParseError::MalformedField(String::from("id")));
+        }
+        
+        let packet_length = if buf.len() < 3 || buf[1] != 0x01 || buf[2] != 0xFA {
+            usize::min(buf.len(), 2)
+        } else {
+            // FE 01 FA, channel name (short length prefixed UTF-16BE) and short length prefixed data
+            let channel_length = match buf.read_u16(3) {
+                Some(len) => 5 + (len as usize) * 2,
+                None => return Err(PacketParseError::LengthMismatch)
+            };
+            match buf.read_u16(channel_length) {
+                Some(len) => channel_length + 2 + len as usize,
+                None => return Err(PacketParseError::LengthMismatch)
+            }
+        };
+        
+        if buf.len() < packet_length {
+            return Err(PacketParseError::LengthMismatch);
+        }
+        
+        Ok((MinecraftPacket {
+            len: (packet_length - 1) as i32,
+            id: LEGACY_PING_ID,
+            cursor: 0,
+            data: buf[1..packet_length].to_vec()
+        }, packet_length))
+    }
+    
+    /// Encodes the packet into its wire format (length prefix, packet id and data).
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut body: Vec<u8> = Vec::new();
+        let id_len = body.write_int(self.id, 0);
+        body.extend_from_slice(&self.data);
+        
+        let mut buf: Vec<u8> = Vec::with_capacity(body.len() + 5);
+        buf.write_int((id_len + self.data.len()) as i32, 0);
+        buf.extend_from_slice(&body);
+        buf
+    }
+    
+    pub fn create_disconnect_packet(msg: &str) -> MinecraftPacket {
         let mut packet = MinecraftPacket::empty();
-        let json = ChatData::new(msg.clone()).to_string();
+        let json = ChatData::new(msg.to_string()).to_string();
         packet.write_string(&json);
         packet
     }
@@ -121,7 +172,7 @@ impl CursoredVarDataReader for MinecraftPacket {
         match self.data.read_int(self.cursor) {
             None => None,
             Some((val, len)) => {
-                self.cursor = self.cursor + len;
+                self.cursor += len;
                 Some(val)
             }
         }
@@ -131,7 +182,7 @@ impl CursoredVarDataReader for MinecraftPacket {
         match self.data.read_long(self.cursor) {
             None => None,
             Some((val, len)) => {
-                self.cursor = self.cursor + len;
+                self.cursor += len;
                 Some(val)
             }
         }
@@ -147,15 +198,31 @@ impl CursoredVarDataReader for MinecraftPacket {
         }
     }
     
+    fn read_i64(&mut self) -> Option<i64> {
+        match self.data.read_i64(self.cursor) {
+            None => None,
+            Some(val) => {
+                self.cursor += 8;
+                Some(val)
+            }
+        }
+    }
+    
     fn read_string(&mut self) -> Option<String> {
         match self.data.read_string(self.cursor) {
             None => None,
             Some((val, len)) => {
-                self.cursor = self.cursor + len;
+                self.cursor += len;
                 Some(val)
             }
         }
     }
+    
+    fn read_remaining(&mut self) -> Vec<u8> {
+        let start = usize::min(self.cursor, self.data.len());
+        self.cursor = self.data.len();
+        self.data[start..].to_vec()
+    }
 }
 
 impl CursoredVarDataWriter for MinecraftPacket {
@@ -181,9 +248,55 @@ impl CursoredVarDataWriter for MinecraftPacket {
         self.len = usize::max(self.len as usize, self.cursor) as i32;
     }
     
-    fn write_string(&mut self, val: &String) {
+    fn write_i64(&mut self, val: i64) {
+        self.data.write_i64(val, self.cursor);
+        self.cursor += 8;
+        self.len = usize::max(self.len as usize, self.cursor) as i32;
+    }
+    
+    fn write_string(&mut self, val: &str) {
         let len = self.data.write_string(val, self.cursor);
         self.cursor += len;
         self.len = usize::max(self.len as usize, self.cursor) as i32;
     }
+    
+    fn write_bool(&mut self, val: bool) {
+        self.write_bytes(&[val as u8]);
+    }
+    
+    fn write_bytes(&mut self, val: &[u8]) {
+        let end = self.cursor + val.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[self.cursor..end].copy_from_slice(val);
+        self.cursor = end;
+        self.len = usize::max(self.len as usize, self.cursor) as i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn check_length_limits() {
+        let mut packet = MinecraftPacket::empty();
+        packet.write_string("hello");
+        let buf = packet.serialize();
+        assert!(MinecraftPacket::parse_packet_limited(buf.clone(), buf.len() - 1).is_ok());
+        assert!(matches!(MinecraftPacket::parse_packet_limited(buf.clone(), buf.len() - 2), Err(PacketParseError::MalformedField(_))));
+        
+        // declared lengths are rejected before the rest of the packet arrives
+        assert!(matches!(MinecraftPacket::parse_packet_limited(vec![0xFF, 0xFF, 0x7F], 264), Err(PacketParseError::MalformedField(_))));
+        assert!(matches!(MinecraftPacket::parse_packet(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Err(PacketParseError::MalformedField(_))));
+        assert!(matches!(MinecraftPacket::parse_packet(vec![0x80, 0x80, 0x80, 0x80, 0x80, 0x01]), Err(PacketParseError::MalformedField(_))));
+        assert!(matches!(MinecraftPacket::parse_packet(vec![0x80, 0x80]), Err(PacketParseError::LengthMismatch)));
+    }
+    
+    #[test]
+    fn check_truncated_string() {
+        let (mut packet, _) = MinecraftPacket::parse_packet(vec![0x03, 0x00, 0x7F, 0x41]).unwrap();
+        assert_eq!(packet.read_string(), None);
+    }
 }
@@ -0,0 +1,332 @@
+use crate::chat::ChatData;
+use crate::packet::{CONTINUE_BIT, MAX_VARINT_LENGTH};
+use crate::reader::VarDataReader;
+use crate::writer::VarDataWriter;
+
+/// First protocol version with the configuration state between login and play (1.20.2).
+const CONFIGURATION_PROTOCOL: u32 = 764;
+/// First protocol version sending text components as NBT instead of JSON (1.20.3).
+const NBT_TEXT_PROTOCOL: u32 = 765;
+/// Longest start of a packet which is inspected, the data length of a compressed packet and the packet id
+/// followed by the threshold of a Set Compression.
+const HEAD_LENGTH: usize = 3 * MAX_VARINT_LENGTH;
+const TAG_STRING: u8 = 8;
+
+// ids of the login state, which are the same in all versions
+const LOGIN_DISCONNECT_ID: i32 = 0x00;
+const ENCRYPTION_REQUEST_ID: i32 = 0x01;
+const LOGIN_SUCCESS_ID: i32 = 0x02;
+const SET_COMPRESSION_ID: i32 = 0x03;
+const LOGIN_ACKNOWLEDGED_ID: i32 = 0x03;
+
+/// State of the client in a forwarded session, as far as the proxy follows it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionPhase {
+    Login,
+    Configuration,
+    Play,
+    /// Encrypted by the origin or not understood, nothing can be sent to the client anymore
+    Opaque
+}
+
+/// Splits a stream into packets, only the start of each packet is kept.
+struct PacketFramer {
+    /// Length prefix of the next packet while it is incomplete
+    prefix: Vec<u8>,
+    /// Start of the current packet
+    head: Vec<u8>,
+    /// Bytes of the current packet which were not received yet
+    remaining: usize
+}
+
+impl PacketFramer {
+    fn new() -> PacketFramer {
+        PacketFramer {
+            prefix: Vec::new(),
+            head: Vec::new(),
+            remaining: 0
+        }
+    }
+    
+    fn at_boundary(&self) -> bool {
+        self.prefix.is_empty() && self.remaining == 0
+    }
+    
+    /// Follows the data and adds the start of each packet it completes to `packets`, stops at the end
+    /// of the current packet when `until_boundary` is set. Returns the number of bytes followed, none
+    /// when the data is not a stream of packets.
+    fn feed(&mut self, data: &[u8], until_boundary: bool, packets: &mut Vec<Vec<u8>>) -> Option<usize> {
+        let mut consumed = 0usize;
+        while consumed < data.len() && !(until_boundary && self.at_boundary()) {
+            if self.remaining == 0 {
+                let byte = data[consumed];
+                consumed += 1;
+                self.prefix.push(byte);
+                if byte & CONTINUE_BIT == 0 {
+                    let (length, _) = self.prefix.read_int(0)?;
+                    self.prefix.clear();
+                    if length <= 0 {
+                        return None
+                    }
+                    self.remaining = length as usize;
+                } else if self.prefix.len() >= MAX_VARINT_LENGTH {
+                    return None
+                }
+            } else {
+                let len = self.remaining.min(data.len() - consumed);
+                let head_len = len.min(HEAD_LENGTH.saturating_sub(self.head.len()));
+                self.head.extend_from_slice(&data[consumed..(consumed + head_len)]);
+                consumed += len;
+                self.remaining -= len;
+                if self.remaining == 0 {
+                    packets.push(std::mem::take(&mut self.head));
+                }
+            }
+        }
+        Some(consumed)
+    }
+}
+
+/// Follows the packets of a forwarded session, so the client can be disconnected with a message in the state it is in.
+/// The origin enables compression and finishes the login, newer clients acknowledge the configuration and play states.
+pub struct PhaseTracker {
+    protocol: u32,
+    phase: SessionPhase,
+    compression: bool,
+    /// The origin sent the Login Success, its following packets belong to the next state
+    logged_in: bool,
+    client: PacketFramer,
+    backend: PacketFramer
+}
+
+impl PhaseTracker {
+    pub fn new(protocol: u32) -> PhaseTracker {
+        PhaseTracker {
+            protocol,
+            phase: SessionPhase::Login,
+            compression: false,
+            logged_in: false,
+            client: PacketFramer::new(),
+            backend: PacketFramer::new()
+        }
+    }
+    
+    pub fn phase(&self) -> SessionPhase {
+        self.phase
+    }
+    
+    /// Whether the client received whole packets only, so a packet of the proxy can be sent to it.
+    /// Sessions which are not followed anymore are never in the middle of a packet.
+    pub fn at_packet_boundary(&self) -> bool {
+        self.phase == SessionPhase::Opaque || self.backend.at_boundary()
+    }
+    
+    /// Id of a packet and the offset of its data, compressed packets can not be inspected.
+    fn read_head(&self, head: &[u8]) -> Option<(i32, usize)> {
+        let head = head.to_vec();
+        let mut offset = 0;
+        if self.compression {
+            let (data_length, len) = head.read_int(0)?;
+            if data_length != 0 {
+                return None
+            }
+            offset = len;
+        }
+        head.read_int(offset).map(|(id, len)| (id, offset + len))
+    }
+    
+    /// Follows data the client sends to the origin. Returns without following it once the client is in the play state.
+    pub fn client_data(&mut self, data: &[u8]) {
+        if self.protocol < CONFIGURATION_PROTOCOL || matches!(self.phase, SessionPhase::Play | SessionPhase::Opaque) {
+            return
+        }
+        let mut packets = Vec::new();
+        if self.client.feed(data, false, &mut packets).is_none() {
+            self.phase = SessionPhase::Opaque;
+            return
+        }
+        // acknowledgements are short, compressed packets are something else
+        let finish_configuration_id = if self.protocol >= 766 { 0x03 } else { 0x02 };
+        for head in packets {
+            match (self.phase, self.read_head(&head)) {
+                (SessionPhase::Login, Some((LOGIN_ACKNOWLEDGED_ID, _))) => self.phase = SessionPhase::Configuration,
+                (SessionPhase::Configuration, Some((id, _))) if id == finish_configuration_id => self.phase = SessionPhase::Play,
+                _ => {}
+            }
+        }
+    }
+    
+    /// Follows data the origin sends to the client, stops at the end of the current packet when `until_boundary`
+    /// is set. Returns the number of bytes which may be passed to the client.
+    pub fn backend_data(&mut self, data: &[u8], until_boundary: bool) -> usize {
+        if self.phase == SessionPhase::Opaque {
+            return data.len()
+        }
+        let mut packets = Vec::new();
+        let consumed = match self.backend.feed(data, until_boundary, &mut packets) {
+            Some(consumed) => consumed,
+            None => {
+                self.phase = SessionPhase::Opaque;
+                return data.len()
+            }
+        };
+        for head in packets {
+            if self.logged_in {
+                break
+            }
+            match self.read_head(&head) {
+                Some((ENCRYPTION_REQUEST_ID, _)) | None => self.phase = SessionPhase::Opaque,
+                Some((SET_COMPRESSION_ID, offset)) => {
+                    // negative threshold disables compression
+                    self.compression = head.to_vec().read_int(offset).is_some_and(|(threshold, _)| threshold >= 0);
+                }
+                Some((LOGIN_SUCCESS_ID, _)) => {
+                    self.logged_in = true;
+                    if self.protocol < CONFIGURATION_PROTOCOL {
+                        self.phase = SessionPhase::Play;
+                    }
+                }
+                Some(_) => {}
+            }
+            if self.phase == SessionPhase::Opaque {
+                return data.len()
+            }
+        }
+        consumed
+    }
+    
+    /// Disconnect packet of the state the client is in, none when the proxy does not know how to frame it.
+    pub fn disconnect_packet(&self, message: &str) -> Option<Vec<u8>> {
+        let (id, reason) = match self.phase {
+            SessionPhase::Login => (LOGIN_DISCONNECT_ID, json_text(message)),
+            SessionPhase::Configuration => (if self.protocol >= 766 { 0x02 } else { 0x01 }, self.text(message)?),
+            SessionPhase::Play => (play_disconnect_id(self.protocol)?, self.text(message)?),
+            SessionPhase::Opaque => return None
+        };
+        let mut body: Vec<u8> = Vec::new();
+        if self.compression {
+            // data length of an uncompressed packet
+            body.write_int(0, 0);
+        }
+        let offset = body.len();
+        body.write_int(id, offset);
+        body.extend_from_slice(&reason);
+        
+        let mut packet: Vec<u8> = Vec::with_capacity(body.len() + MAX_VARINT_LENGTH);
+        packet.write_int(body.len() as i32, 0);
+        packet.extend_from_slice(&body);
+        Some(packet)
+    }
+    
+    fn text(&self, message: &str) -> Option<Vec<u8>> {
+        if self.protocol >= NBT_TEXT_PROTOCOL {
+            nbt_text(message)
+        } else {
+            Some(json_text(message))
+        }
+    }
+}
+
+fn json_text(message: &str) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    data.write_string(&ChatData::new(message.to_string()).to_string(), 0);
+    data
+}
+
+/// Text component as a network NBT string tag, whose value is encoded in modified UTF-8.
+fn nbt_text(message: &str) -> Option<Vec<u8>> {
+    let mut text: Vec<u8> = Vec::with_capacity(message.len());
+    for unit in message.encode_utf16() {
+        match unit {
+            0x0001..=0x007F => text.push(unit as u8),
+            0x0000 | 0x0080..=0x07FF => text.extend_from_slice(&[0xC0 | (unit >> 6) as u8, 0x80 | (unit & 0x3F) as u8]),
+            _ => text.extend_from_slice(&[0xE0 | (unit >> 12) as u8, 0x80 | ((unit >> 6) & 0x3F) as u8, 0x80 | (unit & 0x3F) as u8])
+        }
+    }
+    let mut data = vec![TAG_STRING];
+    data.extend_from_slice(&u16::try_from(text.len()).ok()?.to_be_bytes());
+    data.extend_from_slice(&text);
+    Some(data)
+}
+
+/// Id of the Disconnect packet of the play state, none for versions the proxy does not know.
+fn play_disconnect_id(protocol: u32) -> Option<i32> {
+    match protocol {
+        47 => Some(0x40),
+        107..=340 => Some(0x1A),
+        393..=404 => Some(0x1B),
+        477..=498 => Some(0x1A),
+        573..=578 => Some(0x1B),
+        735..=736 => Some(0x1A),
+        751..=754 => Some(0x19),
+        755..=758 => Some(0x1A),
+        759 => Some(0x17),
+        760 => Some(0x19),
+        761 => Some(0x17),
+        762..=763 => Some(0x1A),
+        764..=765 => Some(0x1B),
+        766..=769 => Some(0x1D),
+        770..=772 => Some(0x1C),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Frames a packet of a session with compression, which is below the threshold.
+    fn uncompressed(id: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![(data.len() + 2) as u8, 0, id];
+        packet.extend_from_slice(data);
+        packet
+    }
+    
+    #[test]
+    fn check_configuration_phases() {
+        let mut tracker = PhaseTracker::new(765);
+        let mut data = vec![3, SET_COMPRESSION_ID as u8, 0x80, 0x02];
+        data.extend(uncompressed(LOGIN_SUCCESS_ID as u8, &[0; 20]));
+        assert_eq!(tracker.backend_data(&data, false), data.len());
+        assert_eq!(tracker.phase(), SessionPhase::Login);
+        
+        tracker.client_data(&uncompressed(LOGIN_ACKNOWLEDGED_ID as u8, &[]));
+        assert_eq!(tracker.phase(), SessionPhase::Configuration);
+        // the origin sends configuration packets, which are not login packets anymore
+        tracker.backend_data(&uncompressed(0x01, &[1, 2, 3]), false);
+        tracker.client_data(&uncompressed(0x02, &[]));
+        assert_eq!(tracker.phase(), SessionPhase::Play);
+        
+        let packet = tracker.disconnect_packet("Bye").unwrap();
+        assert_eq!(packet, [8, 0, 0x1B, TAG_STRING, 0, 3, b'B', b'y', b'e']);
+    }
+    
+    #[test]
+    fn check_packet_boundary() {
+        let mut tracker = PhaseTracker::new(340);
+        let login_success = [3, LOGIN_SUCCESS_ID as u8, 0, 0];
+        tracker.backend_data(&login_success, false);
+        assert_eq!(tracker.phase(), SessionPhase::Play);
+        
+        let chunk = [5, 0x20, 1, 2, 3, 4, 2, 0x1F, 0];
+        assert_eq!(tracker.backend_data(&chunk[..3], false), 3);
+        assert!(!tracker.at_packet_boundary());
+        // only the rest of the packet the client is receiving is passed before a disconnect
+        assert_eq!(tracker.backend_data(&chunk[3..], true), 3);
+        assert!(tracker.at_packet_boundary());
+        assert_eq!(tracker.backend_data(&chunk[6..], true), 0);
+        
+        let packet = tracker.disconnect_packet("Bye").unwrap();
+        assert_eq!(packet[1], 0x1A);
+    }
+    
+    #[test]
+    fn check_encryption() {
+        let mut tracker = PhaseTracker::new(765);
+        assert_eq!(tracker.disconnect_packet("Bye").unwrap()[1], LOGIN_DISCONNECT_ID as u8);
+        tracker.backend_data(&[3, ENCRYPTION_REQUEST_ID as u8, 0, 0], false);
+        assert_eq!(tracker.phase(), SessionPhase::Opaque);
+        assert_eq!(tracker.disconnect_packet("Bye"), None);
+        assert_eq!(tracker.backend_data(&[0xFF; 8], true), 8);
+    }
+}
@@ -1,13 +1,35 @@
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
-use std::time::{Duration, SystemTime};
-use log::{debug, warn};
-use crate::client_packets::HandshakePacket;
-use crate::config::{get_config, BUFFER_SIZE};
-use crate::packet::{MinecraftPacket, PacketParseError};
+use std::time::{Duration, Instant, SystemTime};
+use log::{debug, trace, warn};
+use crate::balancer::{connection_closed, connection_count, connection_opened, select_origin};
+use crate::client_packets::{HandshakePacket, LegacyPingPacket, LoginPluginResponsePacket, LoginStartPacket, PingPacket};
+use crate::config::{get_config, Config, ConfigEndpoint, RejectAction, BUFFER_SIZE, DEFAULT_DISCONNECT_MESSAGE, VERSION_PROTOCOL};
+use crate::geoip::GeoInfo;
+use crate::hostname::{split_fml_marker, split_host_port};
+use crate::forwarding::{bungeecord_address, offline_uuid, velocity_player_info, ForwardingMode, VELOCITY_CHANNEL};
+use crate::proxy_protocol::encode_header;
+use crate::resolver::connect_origin;
+use crate::ratelimit::{report_abuse, ClientConnection};
+use crate::packet::{MinecraftPacket, MinecraftProtocolState, PacketParseError, LEGACY_PING_ID, MAX_VARINT_LENGTH};
+use crate::phase::PhaseTracker;
+use crate::shutdown::is_shutting_down;
+use crate::server_packets::{LegacyKickPacket, LoginPluginRequestPacket, PongPacket, StatusResponsePacket};
+use crate::status::{cached_status, fetch_status, StatusKey};
+use crate::tarpit::tarpit;
+
+const BACKEND_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest handshake, with a server address of up to 255 characters.
+const MAX_HANDSHAKE_LENGTH: usize = 1 + MAX_VARINT_LENGTH + 2 + 255 + 2 + 1;
+/// Status request is empty and ping carries a single long.
+const MAX_STATUS_PACKET_LENGTH: usize = 1 + 8;
+/// Login start of 1.19 clients carries the public key of the player with its signature.
+const MAX_LOGIN_START_LENGTH: usize = 2048;
+/// Time after which a client which is not forwarded yet must keep up with `handshake_min_rate`.
+const MIN_RATE_GRACE_PERIOD: Duration = Duration::from_secs(1);
 
 #[derive(PartialEq)]
 pub enum ProxySocketState {
@@ -15,6 +37,8 @@ pub enum ProxySocketState {
     Closed = 1,
     Status = 2,
     Forward = 3,
+    /// Waiting for the login start packet before the handshake is forwarded
+    Login = 4,
 }
 
 impl Display for ProxySocketState {
@@ -24,6 +48,7 @@ impl Display for ProxySocketState {
             ProxySocketState::Closed => write!(f, "Closed"),
             ProxySocketState::Status => write!(f, "Status"),
             ProxySocketState::Forward => write!(f, "Forward"),
+            ProxySocketState::Login => write!(f, "Login"),
         }
     }
 }
@@ -31,154 +56,792 @@ impl Display for ProxySocketState {
 pub struct ProxySocketInfo {
     pub state: ProxySocketState,
     pub last_activity: u128,
+    pub protocol_version: u32,
+    pub next_state: MinecraftProtocolState,
+    pub endpoint: Option<ConfigEndpoint>,
+    pub origin: Option<String>,
+    pub handshake: Vec<u8>,
+    /// Name of the player from the login start, known only for endpoints with forwarding
+    pub player_name: Option<String>,
+    /// Packets of the backend are inspected until it leaves the login state
+    pub backend_login: bool,
+    /// Message the client is kicked with after the handshake, set for rejected connections
+    pub kick: Option<String>,
+    /// Time the proxy was blocked fetching backend statuses, it does not count against the handshake deadline
+    pub stalled: Duration,
+    /// State of the forwarded login, followed so the client can be disconnected with a message
+    pub phase: Option<PhaseTracker>,
+    /// Message of a disconnect which waits for the client to receive the rest of the current packet
+    pub pending_disconnect: Option<String>,
     
     pub client_addr: SocketAddr,
+    /// Connection counted in the concurrent limit of the client
+    pub connection: Option<ClientConnection>,
+    /// Country and autonomous system of the client address
+    pub geo: GeoInfo,
+    /// Address at which the client connected to the proxy, or to the load balancer in front of it.
+    pub local_addr: SocketAddr,
     pub client_socket: Option<TcpStream>,
     pub client_send_buffer: Vec<u8>,
-    pub client_send_buffer_len: usize,
     
     pub backend_addr: Option<SocketAddr>,
     pub backend_socket: Option<TcpStream>,
     pub backend_send_buffer: Vec<u8>,
-    pub backend_send_buffer_len: usize,
 }
 
 impl ProxySocketInfo {
+    pub fn new(client_addr: SocketAddr, local_addr: SocketAddr, client_socket: TcpStream) -> ProxySocketInfo {
+        ProxySocketInfo {
+            state: ProxySocketState::Handshake,
+            last_activity: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis(),
+            protocol_version: 0,
+            next_state: MinecraftProtocolState::HANDSHAKING,
+            endpoint: None,
+            origin: None,
+            handshake: Vec::new(),
+            player_name: None,
+            backend_login: false,
+            kick: None,
+            stalled: Duration::ZERO,
+            phase: None,
+            pending_disconnect: None,
+            
+            client_addr,
+            connection: None,
+            geo: GeoInfo::default(),
+            local_addr,
+            client_socket: Some(client_socket),
+            client_send_buffer: Vec::with_capacity(BUFFER_SIZE),
+            
+            backend_addr: None,
+            backend_socket: None,
+            backend_send_buffer: Vec::with_capacity(BUFFER_SIZE),
+        }
+    }
+    
     fn switch_state(&mut self, new_state: ProxySocketState) {
         debug!("[{}] switching state to {}", self.client_addr, new_state);
+        if new_state == ProxySocketState::Forward && self.next_state == MinecraftProtocolState::LOGIN {
+            self.phase = Some(PhaseTracker::new(self.protocol_version));
+        }
         self.state = new_state;
         self.last_activity = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
     }
     
-    pub fn handle_client_connection(mut stream: TcpStream, addr: SocketAddr, socket_info_main: Arc<Mutex<ProxySocketInfo>>) {
-        let config = get_config();
+    /// Sends data to the client, data that could not be delivered yet is kept in the client send buffer.
+    fn send_to_client(&mut self, data: &[u8]) {
+        match &mut self.client_socket {
+            Some(client_socket) => {
+                if !self.client_send_buffer.is_empty() {
+                    _ = client_socket.write_all(&self.client_send_buffer);
+                    self.client_send_buffer.clear();
+                }
+                _ = client_socket.write_all(data);
+            }
+            None => self.client_send_buffer.extend_from_slice(data)
+        }
+    }
+    
+    /// Sends data to the backend, data received before the backend is connected is kept in the backend send buffer.
+    fn send_to_backend(&mut self, data: &[u8]) {
+        match &mut self.backend_socket {
+            Some(backend_socket) => {
+                if !self.backend_send_buffer.is_empty() {
+                    _ = backend_socket.write_all(&self.backend_send_buffer);
+                    self.backend_send_buffer.clear();
+                }
+                _ = backend_socket.write_all(data);
+            }
+            None => self.backend_send_buffer.extend_from_slice(data)
+        }
+    }
+    
+    /// Passes data of the client to the backend once the connection is forwarded.
+    fn forward_to_backend(&mut self, data: &[u8]) {
+        if let Some(phase) = &mut self.phase {
+            phase.client_data(data);
+        }
+        self.send_to_backend(data);
+    }
+    
+    /// Passes data of the backend to the client once the connection is forwarded. A pending disconnect
+    /// is sent as soon as the client received the rest of the current packet.
+    fn forward_to_client(&mut self, data: &[u8]) {
+        let len = match &mut self.phase {
+            Some(phase) => phase.backend_data(data, self.pending_disconnect.is_some()),
+            None => data.len()
+        };
+        self.send_to_client(&data[..len]);
+        if let Some(message) = self.pending_disconnect.clone() {
+            if self.phase.as_ref().is_none_or(|phase| phase.at_packet_boundary()) {
+                self.shut_down(&message);
+            }
+        }
+    }
+    
+    fn close(&mut self) {
+        if self.state != ProxySocketState::Closed {
+            self.switch_state(ProxySocketState::Closed);
+        }
+        if let Some(client_socket) = &self.client_socket {
+            _ = client_socket.shutdown(Shutdown::Both);
+        }
+        if let Some(backend_socket) = &self.backend_socket {
+            _ = backend_socket.shutdown(Shutdown::Both);
+        }
+    }
+    
+    fn has_origins(&self) -> bool {
+        self.endpoint.as_ref().is_some_and(|ep| !ep.origins().is_empty())
+    }
+    
+    fn disconnect(&mut self, message: &str) {
+        let packet = MinecraftPacket::create_disconnect_packet(message);
+        self.send_to_client(&packet.serialize());
+        self.close();
+    }
+    
+    /// Closes the connection of a misbehaving client, which counts as several connections in its rate limit.
+    fn close_abusive(&mut self, reason: &str, config: &Config) {
+        debug!("[{}] closing abusive connection: {}", self.client_addr, reason);
+        report_abuse(self.client_addr.ip(), &config.settings);
+        self.close();
+    }
+    
+    /// Time the client has spent on the handshake so far, not counting the time the proxy was blocked itself.
+    fn handshake_elapsed(&self, started: Instant) -> Duration {
+        started.elapsed().saturating_sub(self.stalled)
+    }
+    
+    /// Reads time out at least once per grace period so a silent client is checked for the minimum rate too,
+    /// zero read timeout is not allowed.
+    fn handshake_read_timeout(&self, started: Instant, config: &Config) -> Option<Duration> {
+        let timeout = Duration::from_millis(config.settings.handshake_timeout as u64);
+        let remaining = timeout.saturating_sub(self.handshake_elapsed(started));
+        Some(remaining.clamp(Duration::from_millis(1), MIN_RATE_GRACE_PERIOD))
+    }
+    
+    /// Closes a connection which is not forwarded yet when it missed the handshake deadline or has sent
+    /// less than `handshake_min_rate` since the grace period.
+    fn check_handshake_progress(&mut self, started: Instant, received: usize, config: &Config) {
+        let elapsed = self.handshake_elapsed(started);
+        let min_received = config.settings.handshake_min_rate as f64 * elapsed.as_secs_f64();
+        if elapsed >= Duration::from_millis(config.settings.handshake_timeout as u64) {
+            self.close_abusive("handshake timed out", config);
+        } else if elapsed > MIN_RATE_GRACE_PERIOD && (received as f64) < min_received {
+            let reason = format!("sending too slowly ({} B in {} ms)", received, elapsed.as_millis());
+            self.close_abusive(&reason, config);
+        }
+    }
+    
+    /// Applies the action to a rejected connection, kicked clients are disconnected once they send the handshake.
+    /// Tarpitted clients are handed over to the tarpit, which keeps the connection open after this one is closed.
+    pub fn apply_reject_action(&mut self, action: RejectAction, message: Option<String>, config: &Config) {
+        match action {
+            RejectAction::Drop => self.close(),
+            RejectAction::Kick => self.kick = Some(message.unwrap_or(DEFAULT_DISCONNECT_MESSAGE.to_string())),
+            RejectAction::Tarpit => {
+                if let Some(client_socket) = self.client_socket.take() {
+                    tarpit(client_socket, self.client_addr, &config.settings);
+                }
+                self.close();
+            }
+        }
+    }
+    
+    /// Ends the connection when the proxy shuts down. Forwarded connections get the message in the state the client
+    /// is in, unless the origin encrypted them, and only once the client received the packet it is in the middle of.
+    pub fn shut_down(&mut self, message: &str) {
+        if self.state != ProxySocketState::Forward {
+            self.reject(message);
+            return
+        }
+        let boundary = self.phase.as_ref().is_none_or(|phase| phase.at_packet_boundary());
+        match self.phase.as_ref().and_then(|phase| phase.disconnect_packet(message)) {
+            Some(_) if !boundary => self.pending_disconnect = Some(message.to_string()),
+            Some(packet) => {
+                if let Some(phase) = &self.phase {
+                    debug!("[{}] disconnecting forwarded session in the {:?} state", self.client_addr, phase.phase());
+                }
+                self.pending_disconnect = None;
+                self.send_to_client(&packet);
+                self.close();
+            }
+            None => self.close()
+        }
+    }
+    
+    /// Closes a rejected connection, clients in the login state are disconnected with the message.
+    fn reject(&mut self, message: &str) {
+        // disconnect message can be delivered only in the login state
+        if self.next_state == MinecraftProtocolState::LOGIN {
+            self.disconnect(message);
+        } else {
+            self.close();
+        }
+    }
+    
+    /// Counts the connection towards the endpoint and returns whether the client exceeds the endpoint's
+    /// concurrent limit, which is the global `concurrent_limit` unless the endpoint sets its own.
+    fn exceeds_concurrent_limit(&mut self, endpoint: &ConfigEndpoint, config: &Config) -> bool {
+        let limit = endpoint.concurrent_limit.unwrap_or(config.settings.concurrent_limit);
+        match &mut self.connection {
+            Some(connection) => {
+                connection.assign(endpoint.name());
+                connection.exceeds_limit(limit, &config.settings)
+            }
+            None => false
+        }
+    }
+    
+    /// Processes the handshake and returns an origin the connection should be forwarded to.
+    fn handle_handshake(&mut self, packet: &mut MinecraftPacket, raw: &[u8], config: &Config) -> Option<String> {
+        let handshake_packet = match HandshakePacket::try_from(packet) {
+            Ok(handshake_packet) => handshake_packet,
+            Err(e) => {
+                debug!("[{}] failed to parse handshake: {:?}", self.client_addr, e);
+                self.close();
+                return None
+            }
+        };
+        
+        debug!(
+            "[{}] received packet proto={}, addr={}, port={}, ns={:?}",
+            self.client_addr,
+            handshake_packet.protocol_version,
+            handshake_packet.server_address,
+            handshake_packet.server_port,
+            handshake_packet.next_state
+        );
+        
+        self.protocol_version = handshake_packet.protocol_version;
+        self.next_state = handshake_packet.next_state;
+        self.handshake = raw.to_vec();
+        let (hostname, fml_marker) = split_fml_marker(&handshake_packet.server_address);
+        if config.is_hostname_blocked(hostname) {
+            debug!("[{}] hostname {} is blocked", self.client_addr, hostname);
+            self.apply_reject_action(config.settings.blocklist_action, config.settings.blocklist_message.clone(), config);
+        }
+        if self.state != ProxySocketState::Handshake {
+            return None
+        }
+        if let Some(message) = self.kick.clone() {
+            self.reject(&message);
+            return None
+        }
+        
+        if is_shutting_down() {
+            // server list is answered by the proxy and no new players are accepted
+            if handshake_packet.next_state == MinecraftProtocolState::STATUS {
+                self.switch_state(ProxySocketState::Status);
+            } else {
+                self.disconnect(&config.settings.shutdown_message);
+            }
+            return None
+        }
+        
+        let endpoint = config.resolve_endpoint(Some(hostname.to_string()));
+        // Forge clients may be routed to different origins than vanilla ones
+        let endpoint = endpoint.map(|ep| if fml_marker.is_some() { ep.for_forge() } else { ep });
+        if let Some(endpoint) = endpoint {
+            self.endpoint = Some(endpoint.clone());
+            if endpoint.drop {
+                debug!("[{}] dropping connection", self.client_addr);
+                self.close();
+                return None
+            }
+            if !endpoint.has_valid_origins() {
+                debug!("[{}] hostname {} expands to an invalid origin", self.client_addr, hostname);
+                self.reject(DEFAULT_DISCONNECT_MESSAGE);
+                return None
+            }
+            if endpoint.is_denied(self.client_addr.ip(), &self.geo) {
+                debug!("[{}] denied by the endpoint", self.client_addr);
+                let message = endpoint.deny_message.clone().or(endpoint.message.clone());
+                self.reject(&message.unwrap_or(DEFAULT_DISCONNECT_MESSAGE.to_string()));
+                return None
+            }
+            if self.exceeds_concurrent_limit(&endpoint, config) {
+                debug!("[{}] concurrent_limit exceeded", self.client_addr);
+                let message = config.settings.concurrent_limit_message.clone();
+                self.reject(&message.unwrap_or(DEFAULT_DISCONNECT_MESSAGE.to_string()));
+                return None
+            }
+            
+            let status_request = matches!(handshake_packet.next_state, MinecraftProtocolState::STATUS);
+            if let Some(origin) = select_origin(&endpoint, self.client_addr.ip(), &[]) {
+                self.origin = Some(origin.clone());
+                if status_request && config.settings.cache_size > 0 {
+                    // status is answered by the proxy using the cached backend status
+                    self.switch_state(ProxySocketState::Status);
+                    return None
+                }
+                if !status_request && endpoint.forwarding.is_some() {
+                    // forwarded handshake contains the player UUID which is known only from the login start
+                    self.switch_state(ProxySocketState::Login);
+                    return None
+                }
+                
+                // switch state to forward so all data is forwarded to the proxy, the handshake
+                // is sent once the backend is connected
+                self.switch_state(ProxySocketState::Forward);
+                Some(origin)
+            } else if status_request {
+                self.switch_state(ProxySocketState::Status);
+                None
+            } else {
+                let message = endpoint.message.clone();
+                let message = message.unwrap_or(DEFAULT_DISCONNECT_MESSAGE.to_string());
+                self.disconnect(&message);
+                None
+            }
+        } else if let MinecraftProtocolState::STATUS = handshake_packet.next_state {
+            self.close();
+            None
+        } else {
+            self.disconnect(DEFAULT_DISCONNECT_MESSAGE);
+            None
+        }
+    }
+    
+    /// Forwards the login start, the handshake rewritten by the forwarding mode is sent ahead of it.
+    fn handle_login_start(&mut self, packet: &mut MinecraftPacket, raw: &[u8]) -> Option<String> {
+        let login_packet = match packet.id {
+            0 => LoginStartPacket::try_from(&mut *packet),
+            id => Err(PacketParseError::MalformedField(format!("unexpected packet {}", id)))
+        };
+        let login_packet = match login_packet {
+            Ok(login_packet) => login_packet,
+            Err(e) => {
+                debug!("[{}] failed to parse login start: {:?}", self.client_addr, e);
+                self.close();
+                return None
+            }
+        };
+        debug!("[{}] forwarding player {} ({:032x})", self.client_addr, login_packet.name, offline_uuid(&login_packet.name));
+        // player info of velocity forwarding is sent when the backend asks for it
+        self.backend_login = self.endpoint.as_ref().is_some_and(|ep| ep.forwarding == Some(ForwardingMode::Velocity));
+        self.player_name = Some(login_packet.name);
+        
+        // handshake is sent once the backend is connected
+        self.switch_state(ProxySocketState::Forward);
+        self.forward_to_backend(raw);
+        self.origin.clone()
+    }
+    
+    /// Answers login plugin requests of the backend which belong to the forwarding mode, other packets are
+    /// passed to the client. Returns the number of bytes consumed from `buf`.
+    fn handle_backend_login(&mut self, buf: &[u8]) -> usize {
+        let mut consumed = 0usize;
+        while self.backend_login {
+            let (mut packet, len) = match MinecraftPacket::parse_packet(buf[consumed..].to_vec()) {
+                Ok(res) => res,
+                Err(PacketParseError::MalformedField(field)) => {
+                    debug!("[{}] failed to parse backend packet: MalformedField: {}", self.client_addr, field);
+                    self.close();
+                    return buf.len()
+                }
+                Err(_) => break
+            };
+            let raw = &buf[consumed..(consumed + len)];
+            consumed += len;
+            
+            // login plugin request, any other packet ends the part of login which is inspected
+            if packet.id != 4 {
+                self.backend_login = false;
+                self.forward_to_client(raw);
+                break
+            }
+            match LoginPluginRequestPacket::try_from(&mut packet) {
+                Ok(request) if request.channel == VELOCITY_CHANNEL => {
+                    let secret = self.endpoint.as_ref().and_then(|ep| ep.forwarding_secret.clone());
+                    let name = self.player_name.clone().unwrap_or_default();
+                    let data = match secret {
+                        Some(secret) => Some(velocity_player_info(&secret, self.client_addr.ip(), offline_uuid(&name), &name)),
+                        None => {
+                            warn!("[{}] endpoint uses velocity forwarding without forwarding_secret", self.client_addr);
+                            None
+                        }
+                    };
+                    debug!("[{}] answering {} request (version {:?})", self.client_addr, VELOCITY_CHANNEL, request.data.first());
+                    let response = LoginPluginResponsePacket {
+                        message_id: request.message_id,
+                        data
+                    };
+                    self.send_to_backend(&MinecraftPacket::from(response).serialize());
+                }
+                _ => self.forward_to_client(raw)
+            }
+        }
+        consumed
+    }
+    
+    /// Handshake sent to the origin, rewritten when the endpoint uses host or port rewriting or BungeeCord forwarding.
+    fn forwarded_handshake(&self, origin: &str) -> Vec<u8> {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint,
+            None => return self.handshake.clone()
+        };
+        let bungeecord = endpoint.forwarding == Some(ForwardingMode::Bungeecord) && self.player_name.is_some();
+        if endpoint.rewrite_host.is_none() && endpoint.rewrite_port.is_none() && !bungeecord {
+            return self.handshake.clone()
+        }
+        
+        let handshake_packet = MinecraftPacket::parse_packet(self.handshake.clone()).ok()
+            .and_then(|(mut packet, _)| HandshakePacket::try_from(&mut packet).ok());
+        let mut handshake_packet = match handshake_packet {
+            Some(handshake_packet) => handshake_packet,
+            None => return self.handshake.clone()
+        };
+        
+        // the Forge marker is kept after the rewritten hostname
+        let (hostname, fml_marker) = split_fml_marker(&handshake_packet.server_address);
+        let mut hostname = hostname.to_string();
+        let (origin_host, origin_port) = split_host_port(origin);
+        if let Some(host) = endpoint.rewrite_host.as_ref().and_then(|rewrite| rewrite.value(Some(origin_host))) {
+            hostname = host;
+        }
+        if let Some(port) = endpoint.rewrite_port.as_ref().and_then(|rewrite| rewrite.value(origin_port)) {
+            handshake_packet.server_port = port;
+        }
+        handshake_packet.server_address = match (bungeecord, &self.player_name) {
+            (true, Some(name)) => bungeecord_address(&hostname, self.client_addr.ip(), offline_uuid(name), fml_marker),
+            _ => format!("{}{}", hostname, fml_marker.unwrap_or_default())
+        };
+        MinecraftPacket::from(handshake_packet).serialize()
+    }
+    
+    /// Returns the status of the backend for the client's protocol version and hostname from cache, or fetches
+    /// it when the cached status has expired. Time spent waiting for the status does not count against the client.
+    fn backend_status(&mut self, origin: &str, config: &Config) -> Option<String> {
+        let ttl = Duration::from_millis(config.settings.cache_ttl);
+        let mut fetched = false;
+        let fetch_started = Instant::now();
+        let handshake = self.forwarded_handshake(origin);
+        let mut request = self.proxy_protocol_header();
+        let key = StatusKey::new(origin, &handshake, !request.is_empty());
+        let status = cached_status(key, config.settings.cache_size, ttl, || {
+            fetched = true;
+            request.extend_from_slice(&handshake);
+            fetch_status(origin, &request, BACKEND_CONNECT_TIMEOUT)
+        });
+        self.stalled += fetch_started.elapsed();
+        match status {
+            Ok(json) => {
+                if fetched {
+                    debug!("[{}] fetched status of {}", self.client_addr, origin);
+                } else {
+                    debug!("[{}] serving cached status of {}", self.client_addr, origin);
+                }
+                Some(json)
+            }
+            Err(e) => {
+                warn!("[{}] failed to fetch status of {}: {}", self.client_addr, origin, e);
+                None
+            }
+        }
+    }
+    
+    fn handle_status_packet(&mut self, packet: &mut MinecraftPacket, config: &Config) {
+        match packet.id {
+            0 => { // status request
+                if is_shutting_down() {
+                    let response = StatusResponsePacket::from_motd(self.protocol_version, &config.settings.shutdown_message);
+                    self.send_to_client(&MinecraftPacket::from(response).serialize());
+                    return
+                }
+                let backend_status = self.origin.clone().and_then(|origin| self.backend_status(&origin, config));
+                let motd = self.endpoint.as_ref().and_then(|ep| ep.motd.clone());
+                let response = match (backend_status, motd) {
+                    (Some(json), _) => StatusResponsePacket { json },
+                    (None, Some(motd)) => StatusResponsePacket::from_motd(self.protocol_version, &motd),
+                    (None, None) if !self.has_origins() => StatusResponsePacket::from_motd(self.protocol_version, ""),
+                    (None, None) => {
+                        self.close();
+                        return
+                    }
+                };
+                self.send_to_client(&MinecraftPacket::from(response).serialize());
+            }
+            1 => { // ping request
+                match PingPacket::try_from(packet) {
+                    Ok(ping_packet) => {
+                        let response = PongPacket { payload: ping_packet.payload };
+                        self.send_to_client(&MinecraftPacket::from(response).serialize());
+                    }
+                    Err(e) => debug!("[{}] failed to parse ping: {:?}", self.client_addr, e)
+                }
+                self.close();
+            }
+            id => {
+                debug!("[{}] unexpected packet {} in status state", self.client_addr, id);
+                self.close();
+            }
+        }
+    }
+    
+    /// Answers a pre-1.7 server list ping, only 1.6 clients send the hostname needed for routing
+    /// and older clients are answered by the fallback endpoint.
+    fn handle_legacy_ping(&mut self, packet: &mut MinecraftPacket, config: &Config) {
+        if self.kick.is_some() {
+            self.close();
+            return
+        }
+        
+        let ping_packet = match LegacyPingPacket::try_from(packet) {
+            Ok(ping_packet) => ping_packet,
+            Err(e) => {
+                debug!("[{}] failed to parse legacy ping: {:?}", self.client_addr, e);
+                self.close();
+                return
+            }
+        };
+        
+        debug!(
+            "[{}] received legacy ping version={:?}, proto={:?}, addr={:?}, port={:?}",
+            self.client_addr,
+            ping_packet.version,
+            ping_packet.protocol_version,
+            ping_packet.server_address,
+            ping_packet.server_port
+        );
+        
+        let server_address = ping_packet.server_address.clone();
+        if server_address.as_ref().is_some_and(|hostname| config.is_hostname_blocked(hostname)) {
+            debug!("[{}] hostname {:?} is blocked", self.client_addr, server_address);
+            self.close();
+            return
+        }
+        if is_shutting_down() {
+            let response = LegacyKickPacket::from_motd(&ping_packet.version, &config.settings.shutdown_message);
+            self.send_to_client(&response.serialize());
+            self.close();
+            return
+        }
+        let endpoint = config.resolve_endpoint(server_address.clone());
+        let response = if let Some(endpoint) = endpoint.filter(|ep| !ep.drop && ep.has_valid_origins() && !ep.is_denied(self.client_addr.ip(), &self.geo)) {
+            self.endpoint = Some(endpoint.clone());
+            self.origin = select_origin(&endpoint, self.client_addr.ip(), &[]);
+            
+            // backend status is requested with a modern handshake
+            self.protocol_version = VERSION_PROTOCOL;
+            let handshake_packet = HandshakePacket {
+                protocol_version: VERSION_PROTOCOL,
+                server_address: server_address.unwrap_or_default(),
+                server_port: ping_packet.server_port.unwrap_or(25565),
+                next_state: MinecraftProtocolState::STATUS
+            };
+            self.handshake = MinecraftPacket::from(handshake_packet).serialize();
+            
+            let backend_status = self.origin.clone().and_then(|origin| self.backend_status(&origin, config));
+            let backend_status = backend_status.and_then(|json| LegacyKickPacket::from_json(&ping_packet.version, &json));
+            match (backend_status, &endpoint.motd) {
+                (Some(response), _) => Some(response),
+                (None, Some(motd)) => Some(LegacyKickPacket::from_motd(&ping_packet.version, motd)),
+                (None, None) if !self.has_origins() => Some(LegacyKickPacket::from_motd(&ping_packet.version, "")),
+                (None, None) => None
+            }
+        } else {
+            None
+        };
+        
+        if let Some(response) = response {
+            self.send_to_client(&response.serialize());
+        }
+        self.close();
+    }
+    
+    /// PROXY protocol header sent to the origin before any data, empty if the endpoint does not use it.
+    fn proxy_protocol_header(&self) -> Vec<u8> {
+        match self.endpoint.as_ref().and_then(|ep| ep.proxy_protocol) {
+            Some(version) => encode_header(version, self.client_addr, self.local_addr),
+            None => Vec::new()
+        }
+    }
+    
+    /// Connects to the selected origin, other origins of the endpoint are tried when the connection fails.
+    fn connect_backend(&mut self, origin: String, socket_info_main: &Arc<Mutex<ProxySocketInfo>>) -> Option<JoinHandle<()>> {
+        let mut failed: Vec<String> = Vec::new();
+        let mut next_origin = Some(origin);
+        while let Some(origin) = next_origin {
+            match connect_origin(&origin, BACKEND_CONNECT_TIMEOUT) {
+                Ok((stream, addr)) => {
+                    connection_opened(&origin);
+                    debug!("[{}] connected to backend {} ({} connections)", self.client_addr, origin, connection_count(&origin));
+                    self.origin = Some(origin.clone());
+                    self.backend_addr = Some(addr);
+                    self.backend_socket = Some(stream.try_clone().unwrap());
+                    // PROXY protocol header and the handshake must precede the data received before the backend was connected
+                    let mut preamble = self.proxy_protocol_header();
+                    preamble.extend_from_slice(&self.forwarded_handshake(&origin));
+                    if let Some(backend_socket) = &mut self.backend_socket {
+                        _ = backend_socket.write_all(&preamble);
+                    }
+                    self.send_to_backend(&[]);
+                    
+                    let socket_info_copy = Arc::clone(socket_info_main);
+                    let addr_client = self.client_addr;
+                    return Some(spawn(move || {
+                        debug!("[{}] spawned backend worker", addr_client);
+                        ProxySocketInfo::handle_backend_connection(stream, addr, socket_info_copy);
+                    }))
+                }
+                Err(e) => {
+                    warn!("[{}] failed to connect to backend {}: {}", self.client_addr, origin, e);
+                    failed.push(origin);
+                    next_origin = self.endpoint.as_ref().and_then(|ep| select_origin(ep, self.client_addr.ip(), &failed));
+                }
+            }
+        }
+        
+        if self.next_state == MinecraftProtocolState::LOGIN {
+            let message = self.endpoint.as_ref().and_then(|ep| ep.message.clone());
+            self.disconnect(&message.unwrap_or(DEFAULT_DISCONNECT_MESSAGE.to_string()));
+        } else {
+            self.close();
+        }
+        None
+    }
+    
+    pub fn handle_client_connection(mut stream: TcpStream, addr: SocketAddr, socket_info_main: Arc<Mutex<ProxySocketInfo>>, config: Arc<Config>) {
         let buffer_size = config.settings.client_buffer_size;
         let mut buf: Vec<u8> = vec![0; buffer_size];
         let mut cursor = 0usize;
         let chunk = &mut [0u8; BUFFER_SIZE];
         let mut backend_thread_handle: Option<JoinHandle<_>> = None;
         
-        while let Ok(len) = stream.read(chunk) {
-            debug!("[{}] received {} B chunk", addr, len);
+        // connection must be routed before the deadline, otherwise idle clients would hold their threads forever
+        let started = Instant::now();
+        let mut received = 0usize;
+        let mut packets = 0u32;
+        _ = stream.set_read_timeout(socket_info_main.lock().unwrap().handshake_read_timeout(started, &config));
+        
+        loop {
+            let len = match stream.read(chunk) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    let mut socket_info = socket_info_main.lock().unwrap();
+                    socket_info.check_handshake_progress(started, received, &config);
+                    if socket_info.state == ProxySocketState::Closed {
+                        break
+                    }
+                    _ = stream.set_read_timeout(socket_info.handshake_read_timeout(started, &config));
+                    continue
+                }
+                Err(_) => break
+            };
+            trace!("[{}] received {} B chunk", addr, len);
             
             // lock is acquired only for a time needed to process incoming chunk
             let mut socket_info = socket_info_main.lock().unwrap();
             
             if len == 0 || socket_info.state == ProxySocketState::Closed {
-                _ = stream.shutdown(Shutdown::Both);
+                socket_info.close();
                 break
             }
             
+            if socket_info.state == ProxySocketState::Forward {
+                socket_info.forward_to_backend(&chunk[0..len]);
+                continue
+            }
+            
             if (cursor + len) > config.settings.client_buffer_size {
                 warn!("[{}] client exceeded maximum input length ({} > {})", addr, cursor + len, config.settings.client_buffer_size);
-                socket_info.switch_state(ProxySocketState::Closed);
-                _ = stream.shutdown(Shutdown::Both);
-            } else {
-                buf[cursor..(cursor + len)].copy_from_slice(&chunk[0..len]);
-                cursor = cursor + len;
+                socket_info.close_abusive("input buffer is full", &config);
+                break
             }
             
-            if socket_info.state == ProxySocketState::Forward {
-                let buffer_len = socket_info.backend_send_buffer_len;
-                socket_info.backend_send_buffer[buffer_len..(buffer_len + cursor)].copy_from_slice(&buf[0..cursor]);
-                cursor = 0;
-            } else {
-                // try to parse packets in the buffer
-                while let res = MinecraftPacket::parse_packet(buf[0..cursor].to_vec()) {
-                    if let Ok((packet, len)) = res {
-                        debug!("[{}] accepted {} B packet", addr, len);
+            buf[cursor..(cursor + len)].copy_from_slice(&chunk[0..len]);
+            cursor += len;
+            
+            // try to parse packets in the buffer
+            let mut origin: Option<String> = None;
+            loop {
+                let max_length = match socket_info.state {
+                    ProxySocketState::Handshake => MAX_HANDSHAKE_LENGTH,
+                    ProxySocketState::Status => MAX_STATUS_PACKET_LENGTH,
+                    _ => MAX_LOGIN_START_LENGTH
+                };
+                let res = if socket_info.state == ProxySocketState::Handshake && cursor > 0 && buf[0] == 0xFE {
+                    MinecraftPacket::parse_legacy_ping(buf[0..cursor].to_vec())
+                } else {
+                    MinecraftPacket::parse_packet_limited(buf[0..cursor].to_vec(), max_length)
+                };
+                match res {
+                    Ok((mut packet, len)) => {
+                        trace!("[{}] accepted {} B packet", addr, len);
+                        packets += 1;
+                        if config.settings.client_packets_limit > 0 && packets > config.settings.client_packets_limit {
+                            socket_info.close_abusive("client_packets_limit exceeded", &config);
+                            break
+                        }
+                        let raw = buf[0..len].to_vec();
                         // shift buffer
                         buf.copy_within(len..cursor, 0);
-                        cursor = cursor - len;
+                        cursor -= len;
                         
                         // process packet
-                        if packet.id == 0 { // classic ping
-                            let mut packet = packet;
-                            let handshake_packet = HandshakePacket::try_from(&mut packet).unwrap();
-                            
-                            debug!(
-                                "[{}] received packet proto={}, addr={}, port={}, ns={:?}",
-                                addr,
-                                handshake_packet.protocol_version,
-                                handshake_packet.server_address,
-                                handshake_packet.server_port,
-                                handshake_packet.next_state
-                            );
-                            
-                            let endpoint = config.find_endpoint(handshake_packet.server_address);
-                            if let Some(endpoint) = endpoint {
-                                if let Some(origin) = &endpoint.origin {
-                                    // switch state to forward so all data is forwarded to the proxy
-                                    socket_info.switch_state(ProxySocketState::Forward);
-                                    
-                                    // before stopping the parsing loop, we should move incoming data to the backend send buffer
-                                    let buffer_len = socket_info.backend_send_buffer_len;
-                                    // todo: we shifted `buf` previously so the data should be pull from `packet` instead?
-                                    socket_info.backend_send_buffer[buffer_len..(buffer_len + cursor)].copy_from_slice(&buf[0..cursor]);
-                                    cursor = 0;
-                                    
-                                    // spawn backend worker thread
-                                    if backend_thread_handle.is_none() {
-                                        let socket_info_copy = Arc::clone(&socket_info_main);
-                                        let addr_client = addr.clone();
-                                        let addr: SocketAddr = origin.parse().unwrap();
-                                        match TcpStream::connect_timeout(&addr, Duration::from_secs(5)) {
-                                            Ok(stream) => {
-                                                backend_thread_handle = Some(spawn(move || {
-                                                    debug!("[{}] spawned backend worker", addr_client);
-                                                    ProxySocketInfo::handle_backend_connection(stream, addr, socket_info_copy);
-                                                }));
-                                            }
-                                            Err(_) => {}
-                                        };
-                                    }
-                                } else {
-                                    let message = endpoint.message.clone();
-                                    let message = message.unwrap_or("No further information".to_string());
-                                    let packet = MinecraftPacket::create_disconnect_packet(&message);
-                                    _ = stream.write(&packet.data);
-                                    socket_info.switch_state(ProxySocketState::Closed);
-                                    _ = stream.shutdown(Shutdown::Both);
+                        match socket_info.state {
+                            ProxySocketState::Handshake => {
+                                if packet.id == 0 {
+                                    origin = socket_info.handle_handshake(&mut packet, &raw, &config);
+                                } else if packet.id == LEGACY_PING_ID {
+                                    socket_info.handle_legacy_ping(&mut packet, &config);
                                 }
-                            } else {
-                                let packet = MinecraftPacket::create_disconnect_packet(&"Hello world!".to_string());
-                                _ = stream.write(&packet.data);
-                                socket_info.switch_state(ProxySocketState::Closed);
-                                _ = stream.shutdown(Shutdown::Both);
-                                // todo: send disconnect with default message
                             }
-                        } else if packet.id == 255 { // legacy 2-byte ping
-                            debug!("received legacy ping, ignoring")
+                            ProxySocketState::Status => socket_info.handle_status_packet(&mut packet, &config),
+                            ProxySocketState::Login => origin = socket_info.handle_login_start(&mut packet, &raw),
+                            _ => {}
                         }
-                    } else if let Err(e) = res {
+                    }
+                    Err(e) => {
                         match e {
                             PacketParseError::MalformedField(field) => {
                                 debug!("[{}] failed to parse packet: MalformedField: {}", addr, field);
-                                break
+                                socket_info.close_abusive("malformed packet", &config);
                             },
                             PacketParseError::EmptyBuffer => {
                                 debug!("[{}] failed to parse packet: EmptyBuffer", addr);
-                                break
                             },
                             PacketParseError::LengthMismatch => {
                                 debug!("[{}] failed to parse packet: LengthMismatch", addr);
-                                break
                             }
                         }
+                        break
                     }
                 }
+                
+                if socket_info.state == ProxySocketState::Forward {
+                    // before stopping the parsing loop, we should move incoming data to the backend send buffer
+                    socket_info.forward_to_backend(&buf[0..cursor]);
+                    cursor = 0;
+                    break
+                } else if socket_info.state == ProxySocketState::Closed {
+                    break
+                }
             }
             
-            if socket_info.backend_send_buffer_len > 0 {
-                let buffer = socket_info.backend_send_buffer[0..socket_info.backend_send_buffer_len].to_vec();
-                if let Some(backend_socket) = &mut socket_info.backend_socket {
-                    _ = backend_socket.write(&buffer[..]);
-                    socket_info.backend_send_buffer.clear();
-                    socket_info.backend_send_buffer_len = 0;
+            // spawn backend worker thread
+            if let Some(origin) = origin {
+                if backend_thread_handle.is_none() {
+                    backend_thread_handle = socket_info.connect_backend(origin, &socket_info_main);
                 }
             }
+            
+            if socket_info.state == ProxySocketState::Forward {
+                _ = stream.set_read_timeout(None);
+            } else if socket_info.state != ProxySocketState::Closed {
+                received += len;
+                socket_info.check_handshake_progress(started, received, &config);
+                _ = stream.set_read_timeout(socket_info.handshake_read_timeout(started, &config));
+            }
+            
+            if socket_info.state == ProxySocketState::Closed {
+                break
+            }
         }
         
+        socket_info_main.lock().unwrap().close();
         if let Some(backend_thread) = backend_thread_handle {
             _ = backend_thread.join();
         }
@@ -186,55 +849,200 @@ impl ProxySocketInfo {
     
     pub fn handle_backend_connection(mut stream: TcpStream, addr: SocketAddr, socket_info_main: Arc<Mutex<ProxySocketInfo>>) {
         let config = get_config();
-        // acquire mutable socket info and set backend socket there
-        let mut socket_info = socket_info_main.lock().unwrap();
-        let stream_copy = stream.try_clone().unwrap();
-        socket_info.backend_addr = Some(addr);
-        socket_info.backend_socket = Some(stream_copy);
-        drop(socket_info);
-        
         let buffer_size = config.settings.backend_buffer_size;
         let mut buf: Vec<u8> = vec![0; buffer_size];
         let mut cursor = 0usize;
         let chunk = &mut [0u8; BUFFER_SIZE];
         
         while let Ok(len) = stream.read(chunk) {
-            debug!("[{}] received {} B chunk", addr, len);
+            trace!("[{}] received {} B chunk", addr, len);
             
             let mut socket_info = socket_info_main.lock().unwrap();
             
             if len == 0 || socket_info.state == ProxySocketState::Closed {
-                _ = stream.shutdown(Shutdown::Both);
+                socket_info.close();
                 break
             }
             
             if (cursor + len) > config.settings.backend_buffer_size {
                 warn!("[{}] backend exceeded maximum input length ({} > {})", addr, cursor + len, config.settings.backend_buffer_size);
-                socket_info.switch_state(ProxySocketState::Closed);
-                _ = stream.shutdown(Shutdown::Both);
-                if let Some(client_socket) = &socket_info.client_socket {
-                    _ = client_socket.shutdown(Shutdown::Both);
-                }
-            } else {
-                buf[cursor..(cursor + len)].copy_from_slice(&chunk[0..len]);
-                cursor = cursor + len;
+                socket_info.close();
+                break
             }
             
-            if socket_info.state == ProxySocketState::Forward {
-                let mut send_buffer_len = socket_info.client_send_buffer_len;
-                let send_buffer = socket_info.client_send_buffer[0..send_buffer_len].to_vec();
-                if let Some(client_socket) = &mut socket_info.client_socket {
-                    if send_buffer_len > 0 {
-                        _ = client_socket.write(&send_buffer);
-                        send_buffer_len = 0;
-                    }
-                    _ = client_socket.write(&buf[0..cursor]);
-                    cursor = 0;
-                }
-                socket_info.client_send_buffer_len = send_buffer_len;
-            } else {
-                // TODO: save server status
+            buf[cursor..(cursor + len)].copy_from_slice(&chunk[0..len]);
+            cursor += len;
+            
+            if socket_info.state == ProxySocketState::Forward && socket_info.backend_login {
+                let consumed = socket_info.handle_backend_login(&buf[0..cursor]);
+                buf.copy_within(consumed..cursor, 0);
+                cursor -= consumed;
+            }
+            
+            if socket_info.state == ProxySocketState::Forward && !socket_info.backend_login {
+                socket_info.forward_to_client(&buf[0..cursor]);
+                cursor = 0;
             }
         }
     }
 }
+
+impl Drop for ProxySocketInfo {
+    fn drop(&mut self) {
+        if self.backend_addr.is_some() {
+            if let Some(origin) = &self.origin {
+                connection_closed(origin);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread::sleep;
+    use super::*;
+    use crate::config::{load_test_config, test_config};
+    use crate::reader::CursoredVarDataReader;
+    
+    /// Client side of a local connection and the proxy state of its other side.
+    fn client_connection() -> (TcpStream, ProxySocketInfo) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        (client, ProxySocketInfo::new(addr, listener.local_addr().unwrap(), stream))
+    }
+    
+    #[test]
+    fn check_handshake_min_rate() {
+        let config = test_config("settings: { handshake_min_rate: 16 }");
+        let started = Instant::now() - Duration::from_secs(3);
+        let (_client, mut socket_info) = client_connection();
+        socket_info.check_handshake_progress(started, 64, &config);
+        assert!(socket_info.state == ProxySocketState::Handshake);
+        socket_info.check_handshake_progress(started, 32, &config);
+        assert!(socket_info.state == ProxySocketState::Closed);
+        
+        // time the proxy was blocked is not held against the client
+        let (_client, mut socket_info) = client_connection();
+        socket_info.stalled = Duration::from_millis(2500);
+        socket_info.check_handshake_progress(started, 0, &config);
+        assert!(socket_info.state == ProxySocketState::Handshake);
+    }
+    
+    #[test]
+    fn check_handshake_deadline() {
+        let config = test_config("settings: { handshake_timeout: 1000 }");
+        let started = Instant::now() - Duration::from_millis(1500);
+        let (_client, mut socket_info) = client_connection();
+        socket_info.stalled = Duration::from_millis(1000);
+        let read_timeout = socket_info.handshake_read_timeout(started, &config).unwrap();
+        assert!(read_timeout > Duration::from_millis(400) && read_timeout <= Duration::from_millis(500));
+        socket_info.check_handshake_progress(started, 1000, &config);
+        assert!(socket_info.state == ProxySocketState::Handshake);
+        
+        socket_info.stalled = Duration::ZERO;
+        socket_info.check_handshake_progress(started, 1000, &config);
+        assert!(socket_info.state == ProxySocketState::Closed);
+    }
+    
+    /// Handshake of the client for the hostname, followed by a status request or a login start.
+    fn client_request(socket_info: &mut ProxySocketInfo, hostname: &str, next_state: MinecraftProtocolState, config: &Config) {
+        let handshake = HandshakePacket {
+            protocol_version: VERSION_PROTOCOL,
+            server_address: hostname.to_string(),
+            server_port: 25565,
+            next_state
+        };
+        let raw = MinecraftPacket::from(handshake).serialize();
+        let (mut packet, _) = MinecraftPacket::parse_packet(raw.clone()).unwrap();
+        assert_eq!(socket_info.handle_handshake(&mut packet, &raw, config), None);
+        if next_state == MinecraftProtocolState::STATUS && socket_info.state == ProxySocketState::Status {
+            socket_info.handle_status_packet(&mut MinecraftPacket::empty(), config);
+        }
+    }
+    
+    fn read_string_packet(client: &mut TcpStream) -> Option<String> {
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        let len = client.read(&mut buf).unwrap();
+        let (mut packet, _) = MinecraftPacket::parse_packet(buf[0..len].to_vec()).ok()?;
+        assert_eq!(packet.id, 0);
+        packet.read_string()
+    }
+    
+    #[test]
+    fn check_unknown_hostname() {
+        let config = test_config(r#"
+fallback:
+  motd: "Unknown server"
+  message: "This server does not exist"
+"#);
+        let (mut client, mut socket_info) = client_connection();
+        client_request(&mut socket_info, "unknown.example.net", MinecraftProtocolState::STATUS, &config);
+        assert!(read_string_packet(&mut client).unwrap().contains("Unknown server"));
+        
+        let (mut client, mut socket_info) = client_connection();
+        client_request(&mut socket_info, "unknown.example.net", MinecraftProtocolState::LOGIN, &config);
+        assert!(read_string_packet(&mut client).unwrap().contains("This server does not exist"));
+        assert!(socket_info.state == ProxySocketState::Closed);
+        
+        // without a fallback, logins get the default message and status requests no response
+        let config = test_config("");
+        let (mut client, mut socket_info) = client_connection();
+        client_request(&mut socket_info, "unknown.example.net", MinecraftProtocolState::LOGIN, &config);
+        assert!(read_string_packet(&mut client).unwrap().contains(DEFAULT_DISCONNECT_MESSAGE));
+        
+        let (mut client, mut socket_info) = client_connection();
+        client_request(&mut socket_info, "unknown.example.net", MinecraftProtocolState::STATUS, &config);
+        assert_eq!(client.read(&mut [0u8; 16]).unwrap(), 0);
+    }
+    
+    #[test]
+    fn check_slow_backend_status() {
+        load_test_config();
+        let backend = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin = backend.local_addr().unwrap().to_string();
+        spawn(move || {
+            let (mut stream, _) = backend.accept().unwrap();
+            _ = stream.read(&mut [0u8; BUFFER_SIZE]);
+            sleep(Duration::from_millis(1500));
+            let response = StatusResponsePacket { json: String::from("{}") };
+            _ = stream.write_all(&MinecraftPacket::from(response).serialize());
+        });
+        // the backend takes longer to answer than the client has for its handshake
+        let config = Arc::new(test_config(&format!(r#"
+settings: {{ cache_size: 16, handshake_timeout: 1000 }}
+endpoints:
+  - hostname: slow.example.net
+    origin: "{}"
+"#, origin)));
+        
+        let (mut client, socket_info) = client_connection();
+        let stream = socket_info.client_socket.as_ref().unwrap().try_clone().unwrap();
+        let addr = socket_info.client_addr;
+        let proxy = spawn(move || ProxySocketInfo::handle_client_connection(stream, addr, Arc::new(Mutex::new(socket_info)), config));
+        
+        let handshake = HandshakePacket {
+            protocol_version: VERSION_PROTOCOL,
+            server_address: String::from("slow.example.net"),
+            server_port: 25565,
+            next_state: MinecraftProtocolState::STATUS
+        };
+        let mut request = MinecraftPacket::from(handshake).serialize();
+        request.extend_from_slice(&MinecraftPacket::empty().serialize());
+        client.write_all(&request).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        let len = client.read(&mut buf).unwrap();
+        let (status, _) = MinecraftPacket::parse_packet(buf[0..len].to_vec()).unwrap();
+        assert_eq!(status.id, 0);
+        
+        client.write_all(&[0x09, 0x01, 0, 0, 0, 0, 0, 0, 0, 42]).unwrap();
+        let len = client.read(&mut buf).unwrap();
+        let (mut pong, _) = MinecraftPacket::parse_packet(buf[0..len].to_vec()).unwrap();
+        assert_eq!(pong.id, 1);
+        assert_eq!(PingPacket::try_from(&mut pong).unwrap().payload, 42);
+        proxy.join().unwrap();
+    }
+}
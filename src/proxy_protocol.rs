@@ -0,0 +1,246 @@
+use std::io::{Error, ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::cidr::Cidr;
+
+pub const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2
+}
+
+/// Both addresses of a header must be of the same family, IPv4 addresses are mapped when the other one is IPv6.
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(ip), IpAddr::V6(_)) => (SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), source.port()), destination),
+        (IpAddr::V6(_), IpAddr::V4(ip)) => (source, SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), destination.port())),
+        _ => (source, destination)
+    }
+}
+
+/// Encodes a header of a connection relayed from `source` which connected to the proxy at `destination`.
+pub fn encode_header(version: ProxyProtocolVersion, source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let (source, destination) = same_family(source, destination);
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!("PROXY {} {} {} {} {}\r\n", family, source.ip(), destination.ip(), source.port(), destination.port()).into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut buf = V2_SIGNATURE.to_vec();
+            // version 2, PROXY command
+            buf.push(0x21);
+            let addresses = match (source.ip(), destination.ip()) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    // TCP over IPv4
+                    buf.push(0x11);
+                    [src.octets().to_vec(), dst.octets().to_vec()].concat()
+                }
+                (src, dst) => {
+                    // TCP over IPv6
+                    buf.push(0x21);
+                    let src = match src { IpAddr::V6(ip) => ip, IpAddr::V4(ip) => ip.to_ipv6_mapped() };
+                    let dst = match dst { IpAddr::V6(ip) => ip, IpAddr::V4(ip) => ip.to_ipv6_mapped() };
+                    [src.octets().to_vec(), dst.octets().to_vec()].concat()
+                }
+            };
+            buf.extend_from_slice(&((addresses.len() + 4) as u16).to_be_bytes());
+            buf.extend_from_slice(&addresses);
+            buf.extend_from_slice(&source.port().to_be_bytes());
+            buf.extend_from_slice(&destination.port().to_be_bytes());
+            buf
+        }
+    }
+}
+
+/// Encodes a header of a connection opened by the proxy itself, such as a health check.
+pub fn encode_local_header(version: ProxyProtocolVersion) -> Vec<u8> {
+    match version {
+        ProxyProtocolVersion::V1 => b"PROXY UNKNOWN\r\n".to_vec(),
+        ProxyProtocolVersion::V2 => {
+            let mut buf = V2_SIGNATURE.to_vec();
+            // version 2, LOCAL command, unspecified family and no addresses
+            buf.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+            buf
+        }
+    }
+}
+
+/// Addresses carried by a received header, `source` is the actual client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr
+}
+
+/// Longest possible v1 header including the line ending.
+const V1_MAX_LENGTH: usize = 107;
+
+fn invalid_header(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid PROXY protocol header: {}", reason))
+}
+
+/// Reads a v1 or v2 header from the start of the stream, consuming exactly the header bytes.
+/// Returns `None` for headers without addresses (`UNKNOWN` and `LOCAL` connections).
+pub fn read_header<R: Read>(stream: &mut R) -> Result<Option<ProxyHeader>, Error> {
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix)?;
+    
+    if prefix == *b"PROXY " {
+        let mut line = prefix.to_vec();
+        let mut byte = [0u8; 1];
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid_header("v1 header is too long"))
+            }
+            stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        let line = std::str::from_utf8(&line).map_err(|_| invalid_header("v1 header is not ASCII"))?;
+        parse_v1(line)
+    } else if prefix == V2_SIGNATURE[..6] {
+        let mut header = [0u8; 16];
+        header[..6].copy_from_slice(&prefix);
+        stream.read_exact(&mut header[6..])?;
+        if header[..12] != V2_SIGNATURE {
+            return Err(invalid_header("bad v2 signature"))
+        }
+        let mut addresses = vec![0u8; u16::from_be_bytes([header[14], header[15]]) as usize];
+        stream.read_exact(&mut addresses)?;
+        parse_v2(header[12], header[13], &addresses)
+    } else {
+        Err(invalid_header("missing header"))
+    }
+}
+
+/// Whether the peer is a trusted load balancer, whose connections carry the client address in a header.
+pub fn is_trusted(ip: IpAddr, trusted: &[Cidr]) -> bool {
+    trusted.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// Resolves the actual client and destination of an accepted connection. Connections from trusted
+/// load balancers must start with a header, connections from anywhere else are never parsed for one.
+pub fn accept_header(stream: &mut TcpStream, trusted: &[Cidr], timeout: Duration) -> Result<ProxyHeader, Error> {
+    let peer = ProxyHeader {
+        source: stream.peer_addr()?,
+        destination: stream.local_addr()?
+    };
+    if !is_trusted(peer.source.ip(), trusted) {
+        return Ok(peer)
+    }
+    
+    stream.set_read_timeout(Some(timeout))?;
+    let header = read_header(stream)?;
+    stream.set_read_timeout(None)?;
+    Ok(header.unwrap_or(peer))
+}
+
+fn parse_v1(line: &str) -> Result<Option<ProxyHeader>, Error> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.get(1).copied() {
+        Some("UNKNOWN") => Ok(None),
+        Some("TCP4") | Some("TCP6") if fields.len() == 6 => {
+            let ip = |field: &str| field.parse::<IpAddr>().map_err(|_| invalid_header("bad address"));
+            let port = |field: &str| field.parse::<u16>().map_err(|_| invalid_header("bad port"));
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(ip(fields[2])?, port(fields[4])?),
+                destination: SocketAddr::new(ip(fields[3])?, port(fields[5])?)
+            }))
+        }
+        _ => Err(invalid_header("unsupported v1 protocol"))
+    }
+}
+
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> Result<Option<ProxyHeader>, Error> {
+    if version_command >> 4 != 2 {
+        return Err(invalid_header("unsupported version"))
+    }
+    match version_command & 0x0F {
+        // LOCAL, connection opened by the load balancer itself
+        0x00 => return Ok(None),
+        0x01 => {}
+        _ => return Err(invalid_header("unsupported command"))
+    }
+    
+    // any TLVs following the addresses are ignored
+    match family >> 4 {
+        0x01 if addresses.len() >= 12 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&addresses[at..at + 4]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10))
+            }))
+        }
+        0x02 if addresses.len() >= 36 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 16]>::try_from(&addresses[at..at + 16]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34))
+            }))
+        }
+        // unspecified or unix sockets carry no usable address
+        0x00 | 0x03 => Ok(None),
+        _ => Err(invalid_header("bad v2 address block"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    
+    #[test]
+    fn check_encoding_v1() {
+        let source: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let destination: SocketAddr = "192.0.2.1:25565".parse().unwrap();
+        let header = encode_header(ProxyProtocolVersion::V1, source, destination);
+        assert_eq!(header, b"PROXY TCP4 203.0.113.7 192.0.2.1 51234 25565\r\n");
+        
+        let destination: SocketAddr = "[2001:db8::1]:25565".parse().unwrap();
+        let header = encode_header(ProxyProtocolVersion::V1, source, destination);
+        assert_eq!(header, b"PROXY TCP6 ::ffff:203.0.113.7 2001:db8::1 51234 25565\r\n");
+    }
+    
+    #[test]
+    fn check_encoding_v2() {
+        let source: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let destination: SocketAddr = "192.0.2.1:25565".parse().unwrap();
+        let header = encode_header(ProxyProtocolVersion::V2, source, destination);
+        assert_eq!(header[0..12], V2_SIGNATURE);
+        assert_eq!(header[12..16], [0x21, 0x11, 0x00, 0x0C]);
+        assert_eq!(header[16..24], [203, 0, 113, 7, 192, 0, 2, 1]);
+        assert_eq!(header[24..28], [0xC8, 0x22, 0x63, 0xDD]);
+        assert_eq!(encode_local_header(ProxyProtocolVersion::V2).len(), 16);
+    }
+    
+    #[test]
+    fn check_decoding() {
+        let source: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        for destination in ["192.0.2.1:25565", "[2001:db8::1]:25565"] {
+            let destination: SocketAddr = destination.parse().unwrap();
+            for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+                let mut stream = encode_header(version, source, destination);
+                stream.extend_from_slice(&[0x10, 0x00]);
+                let mut stream = Cursor::new(stream);
+                let header = read_header(&mut stream).unwrap().unwrap();
+                assert_eq!(header.source.ip().to_canonical(), source.ip());
+                assert_eq!(header.destination, destination);
+                // the following data is left in the stream
+                assert_eq!(stream.position() as usize, stream.get_ref().len() - 2);
+            }
+        }
+        
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let mut stream = Cursor::new(encode_local_header(version));
+            assert_eq!(read_header(&mut stream).unwrap(), None);
+        }
+        assert!(read_header(&mut Cursor::new(vec![0x10, 0x00, 0xFB, 0x05, 0x0E, 0x6C, 0x6F])).is_err());
+        assert!(read_header(&mut Cursor::new(vec![b'P'; 200])).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::config::ConfigSettings;
+
+/// Connection counts of the current and the previous window of a client.
+struct Window {
+    start: Instant,
+    current: u32,
+    previous: u32
+}
+
+/// Sliding window rate limiter, the count of the previous window is weighted by the part of it which
+/// still overlaps the sliding window. Rejected connections are counted too so clients which keep
+/// reconnecting stay limited.
+pub struct RateLimiter {
+    windows: HashMap<IpAddr, Window>,
+    last_sweep: Instant
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            windows: HashMap::new(),
+            last_sweep: Instant::now()
+        }
+    }
+    
+    /// Records `weight` connections of the client and returns whether it exceeded `limit` connections within `window`.
+    pub fn hit(&mut self, key: IpAddr, weight: u32, limit: u32, window: Duration, now: Instant) -> bool {
+        if now.duration_since(self.last_sweep) > window {
+            // clients idle for two windows have no effect on the limit anymore
+            self.windows.retain(|_, entry| now.duration_since(entry.start) < window * 2);
+            self.last_sweep = now;
+        }
+        
+        let entry = self.windows.entry(key).or_insert(Window {
+            start: now,
+            current: 0,
+            previous: 0
+        });
+        let mut elapsed = now.duration_since(entry.start);
+        if elapsed >= window {
+            let windows_passed = elapsed.as_nanos() / window.as_nanos().max(1);
+            entry.previous = if windows_passed == 1 { entry.current } else { 0 };
+            entry.current = 0;
+            entry.start += window * windows_passed as u32;
+            elapsed = now.duration_since(entry.start);
+        }
+        entry.current = entry.current.saturating_add(weight);
+        
+        let overlap = 1.0 - elapsed.as_secs_f64() / window.as_secs_f64();
+        let count = entry.previous as f64 * overlap + entry.current as f64;
+        count > limit as f64
+    }
+}
+
+static RATE_LIMITER: Lazy<Mutex<RateLimiter>> = Lazy::new(|| {
+    Mutex::new(RateLimiter::new())
+});
+
+/// Clients usually get a whole IPv6 network, so its addresses are limited together by the network prefix.
+pub fn rate_limit_key(ip: IpAddr, ipv6_prefix: u8) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - ipv6_prefix.min(128) as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+        ip => ip
+    }
+}
+
+/// Weight of a connection rejected for misbehaving, such as malformed or oversized packets.
+const ABUSE_PENALTY: u32 = 5;
+
+fn hit(ip: IpAddr, weight: u32, settings: &ConfigSettings) -> bool {
+    if settings.ratelimit == 0 {
+        return false
+    }
+    let key = rate_limit_key(ip, settings.ratelimit_ipv6_prefix);
+    let window = Duration::from_millis(settings.ratelimit_window as u64);
+    RATE_LIMITER.lock().unwrap().hit(key, weight, settings.ratelimit, window, Instant::now())
+}
+
+/// Records a connection from the address, returns whether it is over the limit.
+pub fn check_rate_limit(ip: IpAddr, settings: &ConfigSettings) -> bool {
+    hit(ip, 1, settings)
+}
+
+/// Counts an abusive connection as several connections, so the client is rate limited sooner.
+pub fn report_abuse(ip: IpAddr, settings: &ConfigSettings) {
+    hit(ip, ABUSE_PENALTY, settings);
+}
+
+/// Endpoint a connection was routed to, none until the handshake is received, and the client key.
+type ConcurrentSlot = (Option<String>, IpAddr);
+
+/// Number of open connections of each client address by the endpoint they were routed to.
+/// IPv6 clients are counted by their network.
+static CONCURRENT: Lazy<Mutex<HashMap<ConcurrentSlot, u32>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// Open connection of a client, counted until it is dropped. IPv6 clients share the count by their
+/// network, see [`rate_limit_key`].
+pub struct ClientConnection {
+    ip: IpAddr,
+    key: IpAddr,
+    endpoint: Option<String>
+}
+
+impl ClientConnection {
+    pub fn open(ip: IpAddr, settings: &ConfigSettings) -> ClientConnection {
+        let connection = ClientConnection {
+            ip,
+            key: rate_limit_key(ip, settings.ratelimit_ipv6_prefix),
+            endpoint: None
+        };
+        *CONCURRENT.lock().unwrap().entry(connection.slot()).or_insert(0) += 1;
+        connection
+    }
+    
+    fn slot(&self) -> ConcurrentSlot {
+        (self.endpoint.clone(), self.key)
+    }
+    
+    /// Counts the connection towards the endpoint it was routed to instead of the connections without a handshake.
+    pub fn assign(&mut self, endpoint: &str) {
+        if self.endpoint.as_deref() == Some(endpoint) {
+            return
+        }
+        let mut concurrent = CONCURRENT.lock().unwrap();
+        release(&mut concurrent, &self.slot());
+        self.endpoint = Some(endpoint.to_string());
+        *concurrent.entry(self.slot()).or_insert(0) += 1;
+    }
+    
+    /// Number of open connections of the client to the same endpoint, including this one.
+    pub fn count(&self) -> u32 {
+        CONCURRENT.lock().unwrap().get(&self.slot()).copied().unwrap_or(0)
+    }
+    
+    /// Whether the client has more than `limit` open connections, unlimited when zero. Clients in
+    /// `concurrent_limit_exempt` are never limited.
+    pub fn exceeds_limit(&self, limit: u32, settings: &ConfigSettings) -> bool {
+        limit > 0 && self.count() > limit && !settings.concurrent_limit_exempt.iter().any(|cidr| cidr.contains(self.ip))
+    }
+}
+
+fn release(concurrent: &mut HashMap<ConcurrentSlot, u32>, slot: &ConcurrentSlot) {
+    if let Some(count) = concurrent.get_mut(slot) {
+        *count -= 1;
+        if *count == 0 {
+            concurrent.remove(slot);
+        }
+    }
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        release(&mut CONCURRENT.lock().unwrap(), &self.slot());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    
+    #[test]
+    fn check_sliding_window() {
+        let mut limiter = RateLimiter::new();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let window = Duration::from_secs(1);
+        let start = Instant::now();
+        
+        for _ in 0..3 {
+            assert!(!limiter.hit(client, 1, 3, window, start));
+        }
+        assert!(limiter.hit(client, 1, 3, window, start));
+        // most of the previous window still counts
+        assert!(limiter.hit(client, 1, 3, window, start + Duration::from_millis(1200)));
+        assert!(!limiter.hit(client, 1, 3, window, start + Duration::from_millis(3000)));
+    }
+    
+    #[test]
+    fn check_ipv6_aggregation() {
+        let key = rate_limit_key("2001:db8:1:2:3:4:5:6".parse().unwrap(), 64);
+        assert_eq!(key, "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        assert_eq!(rate_limit_key("2001:db8::1".parse().unwrap(), 0), "::".parse::<IpAddr>().unwrap());
+        assert_eq!(rate_limit_key("::ffff:203.0.113.7".parse().unwrap(), 64), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+    
+    #[test]
+    fn check_concurrent_connections() {
+        let settings = test_config("settings: { concurrent_limit_exempt: [198.51.100.0/24] }").settings;
+        let client: IpAddr = "203.0.113.23".parse().unwrap();
+        let first = ClientConnection::open(client, &settings);
+        let second = ClientConnection::open("::ffff:203.0.113.23".parse().unwrap(), &settings);
+        assert_eq!(second.count(), 2);
+        assert!(second.exceeds_limit(1, &settings));
+        assert!(!second.exceeds_limit(0, &settings));
+        drop(first);
+        assert_eq!(second.count(), 1);
+        drop(second);
+        
+        // addresses of one IPv6 network share the limit
+        let first = ClientConnection::open("2001:db8:7::1".parse().unwrap(), &settings);
+        let second = ClientConnection::open("2001:db8:7::2".parse().unwrap(), &settings);
+        assert_eq!(first.count(), 2);
+        drop(second);
+        assert_eq!(first.count(), 1);
+        
+        let exempt = ClientConnection::open("198.51.100.23".parse().unwrap(), &settings);
+        let _other = ClientConnection::open("198.51.100.23".parse().unwrap(), &settings);
+        assert!(!exempt.exceeds_limit(1, &settings));
+    }
+    
+    #[test]
+    fn check_concurrent_connections_per_endpoint() {
+        let settings = test_config("").settings;
+        let client: IpAddr = "203.0.113.41".parse().unwrap();
+        let open = |endpoint: &str| {
+            let mut connection = ClientConnection::open(client, &settings);
+            connection.assign(endpoint);
+            connection
+        };
+        
+        // each endpoint allows two connections of the client
+        let lobby = [open("lobby.example.net"), open("lobby.example.net")];
+        let survival = [open("survival.example.net"), open("survival.example.net")];
+        assert!(lobby.iter().chain(survival.iter()).all(|connection| !connection.exceeds_limit(2, &settings)));
+        let third = open("lobby.example.net");
+        assert!(third.exceeds_limit(2, &settings));
+        assert_eq!(survival[0].count(), 2);
+        
+        // routed connections no longer count towards the connections without a handshake
+        let pending = ClientConnection::open(client, &settings);
+        assert_eq!(pending.count(), 1);
+        drop(third);
+        assert_eq!(lobby[0].count(), 2);
+    }
+}
@@ -7,7 +7,11 @@ pub trait VarDataReader {
     
     fn read_u16(&self, offset: usize) -> Option<u16>;
     
+    fn read_i64(&self, offset: usize) -> Option<i64>;
+    
     fn read_string(&self, offset: usize) -> Option<(String, usize)>;
+    
+    fn read_utf16_string(&self, offset: usize) -> Option<(String, usize)>;
 }
 
 impl VarDataReader for Vec<u8> {
@@ -23,9 +27,9 @@ impl VarDataReader for Vec<u8> {
             
             let current_byte: i32 = self[cursor] as i32;
             let next: i32 = (current_byte & (SEGMENT_BITS as i32)) << position;
-            value = value | next;
+            value |= next;
             
-            position = position + 7;
+            position += 7;
             cursor += 1;
             
             if (current_byte & (CONTINUE_BIT as i32)) == 0 {
@@ -44,9 +48,9 @@ impl VarDataReader for Vec<u8> {
         loop {
             let current_byte: i64 = self[cursor] as i64;
             let next: i64 = (current_byte & (SEGMENT_BITS as i64)) << position;
-            value = value | next;
+            value |= next;
             
-            position = position + 7;
+            position += 7;
             cursor += 1;
             
             if (current_byte & (CONTINUE_BIT as i64)) == 0 {
@@ -74,12 +78,25 @@ impl VarDataReader for Vec<u8> {
         }
     }
     
+    fn read_i64(&self, offset: usize) -> Option<i64> {
+        if offset + 8 <= self.len() {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&self[offset..(offset + 8)]);
+            Some(i64::from_be_bytes(bytes))
+        } else {
+            None
+        }
+    }
+    
     fn read_string(&self, offset: usize) -> Option<(String, usize)> {
         match self.read_int(offset) {
             None => None,
             Some((str_len, prefix_len)) => {
                 let start = offset + prefix_len;
-                let end = start + (str_len as usize);
+                let end = start + usize::try_from(str_len).ok()?;
+                if end > self.len() {
+                    return None
+                }
                 let slice = self[start..end].to_vec();
                 match String::from_utf8(slice) {
                     Ok(str) => Some((str, prefix_len + (str_len as usize))),
@@ -88,6 +105,24 @@ impl VarDataReader for Vec<u8> {
             }
         }
     }
+    
+    fn read_utf16_string(&self, offset: usize) -> Option<(String, usize)> {
+        let str_len = self.read_u16(offset)? as usize;
+        let start = offset + 2;
+        let end = start + str_len * 2;
+        if end > self.len() {
+            return None
+        }
+        
+        let units: Vec<u16> = self[start..end]
+            .chunks(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect();
+        match String::from_utf16(&units) {
+            Ok(str) => Some((str, end - offset)),
+            Err(_) => Some((String::new(), end - offset)),
+        }
+    }
 }
 
 pub trait CursoredVarDataReader {
@@ -95,9 +130,15 @@ pub trait CursoredVarDataReader {
     
     fn read_int(&mut self) -> Option<i32>;
     
+    #[allow(dead_code)]
     fn read_long(&mut self) -> Option<i64>;
     
     fn read_u16(&mut self) -> Option<u16>;
     
+    fn read_i64(&mut self) -> Option<i64>;
+    
     fn read_string(&mut self) -> Option<String>;
+    
+    /// Reads all data following the cursor.
+    fn read_remaining(&mut self) -> Vec<u8>;
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+use std::fs::metadata;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+use log::{info, warn};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use crate::balancer::reset_round_robin;
+use crate::health::prune_health;
+use crate::resolver::clear_dns_cache;
+use crate::forwarding::warn_forwarding;
+use crate::config::{config_path, get_config, reload_config, Config, ConfigEndpoint, IDLE_INTERVAL};
+use crate::validation::log_warnings;
+
+/// Endpoints which differ between two configs, identified by their hostname.
+#[derive(Debug, Default, PartialEq)]
+struct EndpointChanges {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>
+}
+
+fn endpoint_name(index: usize, endpoint: &ConfigEndpoint) -> String {
+    match (&endpoint.hostname, &endpoint.hostname_regex) {
+        (Some(hostname), _) => hostname.pattern.clone(),
+        (None, Some(regex)) => regex.pattern.clone(),
+        (None, None) => format!("#{}", index)
+    }
+}
+
+fn named_endpoints(config: &Config) -> Vec<(String, &ConfigEndpoint)> {
+    let endpoints = config.endpoints.iter()
+        .enumerate()
+        .map(|(i, endpoint)| (endpoint_name(i, endpoint), endpoint));
+    let fallback = config.fallback.iter().map(|endpoint| (String::from("fallback"), endpoint));
+    endpoints.chain(fallback).collect()
+}
+
+fn endpoint_changes(previous: &Config, current: &Config) -> EndpointChanges {
+    let previous = named_endpoints(previous);
+    let current = named_endpoints(current);
+    let mut changes = EndpointChanges::default();
+    for (name, endpoint) in current.iter() {
+        match previous.iter().find(|(previous_name, _)| previous_name == name) {
+            None => changes.added.push(name.clone()),
+            Some((_, previous_endpoint)) if previous_endpoint != endpoint => changes.changed.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    for (name, _) in previous.iter() {
+        if !current.iter().any(|(current_name, _)| current_name == name) {
+            changes.removed.push(name.clone());
+        }
+    }
+    changes
+}
+
+/// Replaces the config, connections which are already open keep their endpoint and origin.
+fn reload(reason: &str) {
+    let (previous, current) = match reload_config() {
+        Ok(configs) => configs,
+        Err(e) => {
+            warn!("config was not reloaded on {}: {}", reason, e);
+            return
+        }
+    };
+    
+    let changes = endpoint_changes(&previous, &current);
+    info!(
+        "config reloaded on {}, endpoints added: {:?}, removed: {:?}, changed: {:?}",
+        reason,
+        changes.added,
+        changes.removed,
+        changes.changed
+    );
+    // positions of origins in the round-robin state of changed endpoints may have shifted
+    reset_round_robin(&[changes.removed, changes.changed].concat());
+    prune_health(&current);
+    warn_forwarding(&current);
+    log_warnings(&current);
+    
+    if previous.settings.dns_servers != current.settings.dns_servers {
+        info!("dns_servers changed, cached DNS records are discarded");
+        clear_dns_cache();
+    }
+    if previous.settings.listen != current.settings.listen {
+        warn!("listen changed, the proxy keeps listening on the previous port until it is restarted");
+    }
+    if previous.settings.geoip_databases != current.settings.geoip_databases {
+        warn!("geoip_databases changed, the loaded databases are used until the proxy is restarted");
+    }
+}
+
+fn modified_time() -> Option<SystemTime> {
+    metadata(config_path()).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Reloads the config on SIGHUP and, when `config_watch_interval` is set, whenever the config file is modified.
+pub fn spawn_config_reloader() {
+    let mut signals = Signals::new([SIGHUP]).expect("Failed to register signal handlers");
+    spawn(move || {
+        for _ in signals.forever() {
+            reload("SIGHUP");
+        }
+    });
+    
+    spawn(|| {
+        let mut last_modified = modified_time();
+        loop {
+            let interval = get_config().settings.config_watch_interval;
+            if interval == 0 {
+                sleep(IDLE_INTERVAL);
+                continue
+            }
+            sleep(Duration::from_millis(interval));
+            
+            let modified = modified_time();
+            if modified != last_modified {
+                last_modified = modified;
+                reload("file change");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    
+    #[test]
+    fn check_endpoint_changes() {
+        let previous = test_config(r#"
+endpoints:
+  - hostname: lobby.example.net
+    origin: 10.0.0.1:25565
+  - hostname: survival.example.net
+    origin: 10.0.0.2:25565
+  - hostname_regex: '(?P<id>[0-9]+)\.example\.net'
+    origin: 10.0.1.{id}:25565
+"#);
+        let current = test_config(r#"
+endpoints:
+  - hostname: lobby.example.net
+    origin: 10.0.0.1:25565
+  - hostname: survival.example.net
+    origin: 10.0.0.3:25565
+  - hostname: creative.example.net
+    origin: 10.0.0.4:25565
+fallback:
+  motd: Unknown server
+"#);
+        
+        assert_eq!(endpoint_changes(&previous, &current), EndpointChanges {
+            added: vec![String::from("creative.example.net"), String::from("fallback")],
+            removed: vec![String::from(r"(?P<id>[0-9]+)\.example\.net")],
+            changed: vec![String::from("survival.example.net")]
+        });
+        assert_eq!(endpoint_changes(&current, &current), EndpointChanges::default());
+    }
+}
@@ -0,0 +1,495 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::config::get_config;
+use crate::hostname::parse_origin;
+
+/// Port of origins which have neither a port nor an SRV record.
+pub const DEFAULT_PORT: u16 = 25565;
+const SRV_PREFIX: &str = "_minecraft._tcp.";
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const MAX_MESSAGE_SIZE: usize = 4096;
+/// Limit of compression pointers in a single name, so a malicious response can not loop forever.
+const MAX_POINTERS: usize = 32;
+
+/// Time for which a name without any records is cached.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+/// Longest time records are cached, regardless of their TTL.
+const MAX_TTL: Duration = Duration::from_secs(3600);
+/// Expired records are swept once the cache grows over this number of names.
+const MAX_CACHE_ENTRIES: usize = 1024;
+/// Delay after which the next address is tried while the previous attempt is still pending (RFC 8305).
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, PartialEq)]
+enum RecordData {
+    Address(IpAddr),
+    Service {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Record {
+    ttl: u32,
+    data: RecordData
+}
+
+fn encode_name(message: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid hostname {}", name)))
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    Ok(())
+}
+
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, Error> {
+    let mut message = Vec::with_capacity(64);
+    message.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    encode_name(&mut message, name)?;
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&1u16.to_be_bytes());
+    Ok(message)
+}
+
+/// Reads a possibly compressed name, returns it with the offset following it.
+fn read_name(message: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut cursor = offset;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message.get(cursor)? as usize;
+        if len & 0xC0 == 0xC0 {
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return None
+            }
+            end.get_or_insert(cursor + 2);
+            cursor = ((len & 0x3F) << 8) | *message.get(cursor + 1)? as usize;
+        } else if len == 0 {
+            end.get_or_insert(cursor + 1);
+            break
+        } else if len <= 63 {
+            let label = message.get((cursor + 1)..(cursor + 1 + len))?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            cursor += 1 + len;
+        } else {
+            return None
+        }
+    }
+    Some((labels.join("."), end?))
+}
+
+/// Parses the answers of the given type, a name which does not exist has no records.
+fn parse_response(message: &[u8], record_type: u16) -> Result<Vec<Record>, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "malformed DNS response");
+    let u16_at = |offset: usize| message.get(offset..(offset + 2)).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])).ok_or_else(invalid);
+    
+    let flags = u16_at(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(invalid())
+    }
+    match flags & 0x000F {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => return Err(Error::other(format!("DNS server failure, rcode {}", rcode)))
+    }
+    
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+    let mut cursor = 12;
+    for _ in 0..questions {
+        let (_, next) = read_name(message, cursor).ok_or_else(invalid)?;
+        cursor = next + 4;
+    }
+    
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let (_, next) = read_name(message, cursor).ok_or_else(invalid)?;
+        let answer_type = u16_at(next)?;
+        let ttl = (u16_at(next + 4)? as u32) << 16 | u16_at(next + 6)? as u32;
+        let length = u16_at(next + 8)? as usize;
+        let start = next + 10;
+        let data = message.get(start..(start + length)).ok_or_else(invalid)?;
+        cursor = start + length;
+        
+        // answers may contain the CNAME records which led to the requested ones
+        let data = match answer_type {
+            _ if answer_type != record_type => continue,
+            TYPE_A if length == 4 => RecordData::Address(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).unwrap()))),
+            TYPE_AAAA if length == 16 => RecordData::Address(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()))),
+            TYPE_SRV if length > 6 => RecordData::Service {
+                priority: u16_at(start)?,
+                weight: u16_at(start + 2)?,
+                port: u16_at(start + 4)?,
+                target: read_name(message, start + 6).ok_or_else(invalid)?.0
+            },
+            _ => return Err(invalid())
+        };
+        records.push(Record {
+            ttl,
+            data
+        });
+    }
+    Ok(records)
+}
+
+/// Records of a name and type with the time they expire.
+type CacheEntry = (Vec<Record>, Instant);
+
+/// DNS client which caches the records of each name and type for their TTL.
+pub struct Resolver {
+    cache: Mutex<HashMap<(String, u16), CacheEntry>>
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            cache: Mutex::new(HashMap::new())
+        }
+    }
+    
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+    
+    fn exchange(server: SocketAddr, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        let local: SocketAddr = if server.is_ipv4() { (Ipv4Addr::UNSPECIFIED, 0).into() } else { (Ipv6Addr::UNSPECIFIED, 0).into() };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        socket.send(query)?;
+        
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::new(ErrorKind::TimedOut, format!("DNS server {} did not answer", server)))
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let len = socket.recv(&mut buf)?;
+            // responses to other queries are ignored
+            if buf[..len].starts_with(&query[0..2]) {
+                return Ok(buf[..len].to_vec())
+            }
+        }
+    }
+    
+    /// Queries the servers in order until one of them answers.
+    fn query(&self, name: &str, record_type: u16, servers: &[SocketAddr], timeout: Duration) -> Result<Vec<Record>, Error> {
+        let key = (name.to_ascii_lowercase(), record_type);
+        if let Some((records, expires)) = self.cache.lock().unwrap().get(&key) {
+            if *expires > Instant::now() {
+                return Ok(records.clone())
+            }
+        }
+        
+        let query = encode_query(rand::random(), name, record_type)?;
+        let mut last_error = Error::new(ErrorKind::NotFound, "no DNS servers are configured");
+        for server in servers {
+            match Self::exchange(*server, &query, timeout).and_then(|response| parse_response(&response, record_type)) {
+                Ok(records) => {
+                    let ttl = records.iter()
+                        .map(|record| Duration::from_secs(record.ttl as u64))
+                        .min()
+                        .unwrap_or(NEGATIVE_TTL)
+                        .min(MAX_TTL);
+                    let mut cache = self.cache.lock().unwrap();
+                    if cache.len() >= MAX_CACHE_ENTRIES {
+                        let now = Instant::now();
+                        cache.retain(|_, (_, expires)| *expires > now);
+                    }
+                    cache.insert(key, (records.clone(), Instant::now() + ttl));
+                    return Ok(records)
+                }
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+    
+    fn lookup_host(&self, host: &str, port: u16, servers: &[SocketAddr], timeout: Duration) -> Result<Vec<SocketAddr>, Error> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)])
+        }
+        if host.eq_ignore_ascii_case("localhost") {
+            return Ok(vec![SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port), SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)])
+        }
+        
+        let mut addresses = Vec::new();
+        let mut last_error = None;
+        for record_type in [TYPE_AAAA, TYPE_A] {
+            match self.query(host, record_type, servers, timeout) {
+                Ok(records) => addresses.extend(records.iter().filter_map(|record| match record.data {
+                    RecordData::Address(ip) => Some(SocketAddr::new(ip, port)),
+                    _ => None
+                })),
+                Err(e) => last_error = Some(e)
+            }
+        }
+        if addresses.is_empty() {
+            return Err(last_error.unwrap_or(Error::new(ErrorKind::NotFound, format!("{} has no addresses", host))))
+        }
+        Ok(addresses)
+    }
+    
+    /// Resolves an origin to its addresses. Origins without a port are looked up by their
+    /// `_minecraft._tcp` SRV record first, like the vanilla client does.
+    pub fn resolve(&self, origin: &str, servers: &[SocketAddr], timeout: Duration) -> Result<Vec<SocketAddr>, Error> {
+        let (host, port) = parse_origin(origin).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        if let Some(port) = port {
+            return self.lookup_host(&host, port, servers, timeout)
+        }
+        if host.parse::<IpAddr>().is_ok() || host.eq_ignore_ascii_case("localhost") {
+            return self.lookup_host(&host, DEFAULT_PORT, servers, timeout)
+        }
+        
+        let mut services: Vec<(u16, u16, u16, String)> = self.query(&format!("{}{}", SRV_PREFIX, host), TYPE_SRV, servers, timeout)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|record| match record.data {
+                // target "." means the service is not available at this name
+                RecordData::Service { priority, weight, port, target } if !target.is_empty() => Some((priority, weight, port, target)),
+                _ => None
+            })
+            .collect();
+        services.sort_by_key(|(priority, weight, _, _)| (*priority, Reverse(*weight)));
+        
+        let mut addresses = Vec::new();
+        for (_, _, port, target) in services.iter() {
+            if let Ok(found) = self.lookup_host(target, *port, servers, timeout) {
+                addresses.extend(found);
+            }
+        }
+        if addresses.is_empty() {
+            return self.lookup_host(&host, DEFAULT_PORT, servers, timeout)
+        }
+        Ok(addresses)
+    }
+}
+
+static RESOLVER: Lazy<Resolver> = Lazy::new(Resolver::new);
+
+/// Name servers of the system, used when `dns_servers` is not set.
+static SYSTEM_SERVERS: Lazy<Vec<SocketAddr>> = Lazy::new(|| {
+    read_to_string("/etc/resolv.conf").map(|conf| parse_resolv_conf(&conf)).unwrap_or_default()
+});
+
+fn parse_resolv_conf(conf: &str) -> Vec<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["nameserver", address, ..] => address.parse::<IpAddr>().ok(),
+            _ => None
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect()
+}
+
+/// Name server written either as an address using port 53 or as an address with a port.
+pub fn parse_dns_server(server: &str) -> Option<SocketAddr> {
+    match server.parse::<IpAddr>() {
+        Ok(ip) => Some(SocketAddr::new(ip, 53)),
+        Err(_) => server.parse().ok()
+    }
+}
+
+/// Forgets the cached records, such as when other name servers are configured.
+pub fn clear_dns_cache() {
+    RESOLVER.clear();
+}
+
+pub fn resolve_origin(origin: &str) -> Result<Vec<SocketAddr>, Error> {
+    let config = get_config();
+    let servers: Vec<SocketAddr> = match config.settings.dns_servers.is_empty() {
+        true => SYSTEM_SERVERS.clone(),
+        false => config.settings.dns_servers.iter().filter_map(|server| parse_dns_server(server)).collect()
+    };
+    RESOLVER.resolve(origin, &servers, Duration::from_millis(config.settings.dns_timeout))
+}
+
+/// Alternates between the address families, starting with the family of the first address (RFC 8305).
+fn interleave_families(addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_ipv6 = addresses.first().is_some_and(|addr| addr.is_ipv6());
+    let (mut preferred, mut other): (VecDeque<SocketAddr>, VecDeque<SocketAddr>) = addresses.iter()
+        .partition(|addr| addr.is_ipv6() == first_ipv6);
+    let mut interleaved = Vec::with_capacity(addresses.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        interleaved.extend(preferred.pop_front());
+        interleaved.extend(other.pop_front());
+    }
+    interleaved
+}
+
+/// Connects to the first address which accepts the connection. Attempts are started one after
+/// another in a short delay without waiting for the previous ones to fail (Happy Eyeballs).
+pub fn connect_any(addresses: &[SocketAddr], timeout: Duration) -> Result<(TcpStream, SocketAddr), Error> {
+    let addresses = interleave_families(addresses);
+    let deadline = Instant::now() + timeout;
+    let (sender, receiver) = channel();
+    let mut next = 0;
+    let mut pending = 0;
+    let mut last_error = Error::new(ErrorKind::NotFound, "origin has no addresses");
+    
+    loop {
+        if next < addresses.len() {
+            let addr = addresses[next];
+            let sender = sender.clone();
+            // connections which succeed after another attempt won are dropped with the closed channel
+            spawn(move || _ = sender.send((addr, TcpStream::connect_timeout(&addr, timeout))));
+            next += 1;
+            pending += 1;
+        }
+        if pending == 0 {
+            return Err(last_error)
+        }
+        
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let wait = if next < addresses.len() { CONNECTION_ATTEMPT_DELAY.min(remaining) } else { remaining };
+        match receiver.recv_timeout(wait) {
+            Ok((addr, Ok(stream))) => return Ok((stream, addr)),
+            Ok((_, Err(e))) => {
+                pending -= 1;
+                last_error = e;
+            }
+            Err(RecvTimeoutError::Timeout) if remaining.is_zero() => {
+                return Err(Error::new(ErrorKind::TimedOut, "connection to origin timed out"))
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+pub fn connect_origin(origin: &str, timeout: Duration) -> Result<(TcpStream, SocketAddr), Error> {
+    let addresses = resolve_origin(origin)?;
+    connect_any(&addresses, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    /// Stand-in DNS server answering from a fixed set of records, it counts the queries it received.
+    fn spawn_dns_server(records: Vec<(&'static str, u16, Vec<u8>)>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, client)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let query = &buf[..len];
+                let (name, next) = read_name(query, 12).unwrap();
+                let record_type = u16::from_be_bytes([query[next], query[next + 1]]);
+                let answers: Vec<&Vec<u8>> = records.iter()
+                    .filter(|(record_name, answer_type, _)| *record_name == name && *answer_type == record_type)
+                    .map(|(_, _, data)| data)
+                    .collect();
+                
+                let mut response = query[0..2].to_vec();
+                let rcode = if records.iter().any(|(record_name, _, _)| *record_name == name) { 0 } else { 3 };
+                response.extend_from_slice(&[0x81, 0x80 | rcode, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+                response.extend_from_slice(&query[12..(next + 4)]);
+                for data in answers {
+                    // owner name is a pointer to the question
+                    response.extend_from_slice(&[0xC0, 12]);
+                    response.extend_from_slice(&record_type.to_be_bytes());
+                    response.extend_from_slice(&[0, 1, 0, 0, 1, 44]);
+                    response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                    response.extend_from_slice(data);
+                }
+                _ = socket.send_to(&response, client);
+            }
+        });
+        (addr, queries)
+    }
+    
+    fn service(priority: u16, weight: u16, port: u16, target: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&priority.to_be_bytes());
+        data.extend_from_slice(&weight.to_be_bytes());
+        data.extend_from_slice(&port.to_be_bytes());
+        encode_name(&mut data, target).unwrap();
+        data
+    }
+    
+    #[test]
+    fn check_resolution() {
+        let (server, queries) = spawn_dns_server(vec![
+            ("_minecraft._tcp.mc.test", TYPE_SRV, service(20, 0, 25590, "backup.mc.test")),
+            ("_minecraft._tcp.mc.test", TYPE_SRV, service(10, 5, 25570, "main.mc.test")),
+            ("main.mc.test", TYPE_A, vec![127, 0, 0, 2]),
+            ("backup.mc.test", TYPE_AAAA, Ipv6Addr::LOCALHOST.octets().to_vec()),
+            ("plain.test", TYPE_A, vec![127, 0, 0, 3]),
+            ("plain.test", TYPE_A, vec![127, 0, 0, 4])
+        ]);
+        let resolver = Resolver::new();
+        let timeout = Duration::from_secs(2);
+        let resolve = |origin: &str| resolver.resolve(origin, &[server], timeout);
+        
+        assert_eq!(resolve("mc.test").unwrap(), vec!["127.0.0.2:25570".parse().unwrap(), "[::1]:25590".parse().unwrap()]);
+        assert_eq!(resolve("plain.test").unwrap(), vec!["127.0.0.3:25565".parse().unwrap(), "127.0.0.4:25565".parse().unwrap()]);
+        assert_eq!(resolve("Plain.test:25580").unwrap(), vec!["127.0.0.3:25580".parse().unwrap(), "127.0.0.4:25580".parse().unwrap()]);
+        assert_eq!(resolve("[2001:db8::1]").unwrap(), vec!["[2001:db8::1]:25565".parse().unwrap()]);
+        assert!(resolve("missing.test:25565").is_err());
+        
+        // all records are cached, including the missing ones
+        let count = queries.load(Ordering::SeqCst);
+        resolve("mc.test").unwrap();
+        resolve("plain.test").unwrap();
+        assert!(resolve("missing.test:25565").is_err());
+        assert_eq!(queries.load(Ordering::SeqCst), count);
+    }
+    
+    #[test]
+    fn check_malformed_names() {
+        // pointer to itself
+        assert_eq!(read_name(&[0xC0, 0], 0), None);
+        assert_eq!(read_name(&[3, b'f', b'o'], 0), None);
+        assert_eq!(read_name(&[2, b'm', b'c', 0, 0xC0, 0], 4), Some((String::from("mc"), 6)));
+        assert!(encode_query(1, "a..example.net", TYPE_A).is_err());
+        
+        let conf = "# local resolver\nnameserver 127.0.0.53\nnameserver fe80::1%eth0\nnameserver ::1\nsearch example.net";
+        assert_eq!(parse_resolv_conf(conf), vec!["127.0.0.53:53".parse().unwrap(), "[::1]:53".parse().unwrap()]);
+    }
+    
+    #[test]
+    fn check_happy_eyeballs() {
+        let v4: SocketAddr = "192.0.2.1:25565".parse().unwrap();
+        let v4_other: SocketAddr = "192.0.2.2:25565".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:25565".parse().unwrap();
+        assert_eq!(interleave_families(&[v4, v4_other, v6]), vec![v4, v6, v4_other]);
+        
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (_, addr) = connect_any(&[closed, open], Duration::from_secs(2)).unwrap();
+        assert_eq!(addr, open);
+        assert!(connect_any(&[closed], Duration::from_secs(2)).is_err());
+        assert!(connect_any(&[], Duration::from_secs(2)).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use serde_json::{json, Value};
+use crate::chat::{to_plain_text, ChatData};
+use crate::client_packets::LegacyPingVersion;
+use crate::config::VERSION_PROTOCOL_NAME;
+use crate::packet::{MinecraftPacket, PacketParseError};
+use crate::reader::CursoredVarDataReader;
+use crate::writer::{CursoredVarDataWriter, VarDataWriter};
+
+/// Protocol version advertised to pre-1.7 clients, it never matches so the version name is displayed instead.
+const LEGACY_PROTOCOL_VERSION: i32 = 127;
+
+pub struct StatusResponsePacket {
+    pub json: String
+}
+
+impl StatusResponsePacket {
+    /// Builds a server list entry that advertises the client's own protocol version,
+    /// so the description is displayed without an "outdated" warning.
+    pub fn from_motd(protocol_version: u32, motd: &str) -> StatusResponsePacket {
+        let json = json!({
+            "version": {
+                "name": VERSION_PROTOCOL_NAME,
+                "protocol": protocol_version
+            },
+            "players": {
+                "max": 0,
+                "online": 0
+            },
+            "description": ChatData::new(motd.to_string())
+        });
+        StatusResponsePacket {
+            json: json.to_string()
+        }
+    }
+}
+
+impl From<StatusResponsePacket> for MinecraftPacket {
+    fn from(value: StatusResponsePacket) -> Self {
+        let mut packet = MinecraftPacket::empty();
+        packet.write_string(&value.json);
+        
+        packet
+    }
+}
+
+pub struct PongPacket {
+    pub payload: i64
+}
+
+impl From<PongPacket> for MinecraftPacket {
+    fn from(value: PongPacket) -> Self {
+        let mut packet = MinecraftPacket::empty();
+        packet.id = 1;
+        packet.write_i64(value.payload);
+        
+        packet
+    }
+}
+
+/// Custom query sent by the origin during login, identified by its channel.
+pub struct LoginPluginRequestPacket {
+    pub message_id: i32,
+    pub channel: String,
+    pub data: Vec<u8>
+}
+
+impl TryFrom<&mut MinecraftPacket> for LoginPluginRequestPacket {
+    type Error = PacketParseError;
+    
+    fn try_from(packet: &mut MinecraftPacket) -> Result<Self, Self::Error> {
+        CursoredVarDataReader::reset_cursor(packet);
+        let f1 = packet.read_int().ok_or(PacketParseError::MalformedField(String::from("message_id")))?;
+        let f2 = packet.read_string().ok_or(PacketParseError::MalformedField(String::from("channel")))?;
+        let f3 = packet.read_remaining();
+        Ok(LoginPluginRequestPacket {
+            message_id: f1,
+            channel: f2,
+            data: f3
+        })
+    }
+}
+
+/// Response to a pre-1.7 server list ping, sent as a `0xFF` kick packet.
+pub struct LegacyKickPacket {
+    pub message: String
+}
+
+impl LegacyKickPacket {
+    pub fn from_status(version: &LegacyPingVersion, version_name: &str, motd: &str, online: i64, max: i64) -> LegacyKickPacket {
+        let message = match version {
+            LegacyPingVersion::Beta => {
+                // beta clients split the response by '§' so formatting codes can not be used
+                let motd: String = motd.split('§')
+                    .enumerate()
+                    .map(|(i, part)| if i == 0 { part } else { part.get(1..).unwrap_or_default() })
+                    .collect();
+                format!("{}§{}§{}", motd, online, max)
+            }
+            _ => format!("§1\0{}\0{}\0{}\0{}\0{}", LEGACY_PROTOCOL_VERSION, version_name, motd, online, max)
+        };
+        LegacyKickPacket {
+            message
+        }
+    }
+    
+    pub fn from_motd(version: &LegacyPingVersion, motd: &str) -> LegacyKickPacket {
+        LegacyKickPacket::from_status(version, VERSION_PROTOCOL_NAME, motd, 0, 0)
+    }
+    
+    /// Converts a JSON status response of a modern server, returns `None` if the response is malformed.
+    pub fn from_json(version: &LegacyPingVersion, json: &str) -> Option<LegacyKickPacket> {
+        let status: Value = serde_json::from_str(json).ok()?;
+        let version_name = status["version"]["name"].as_str().unwrap_or(VERSION_PROTOCOL_NAME);
+        let motd = to_plain_text(&status["description"]);
+        let online = status["players"]["online"].as_i64().unwrap_or(0);
+        let max = status["players"]["max"].as_i64().unwrap_or(0);
+        Some(LegacyKickPacket::from_status(version, version_name, &motd, online, max))
+    }
+    
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0xFF];
+        buf.write_utf16_string(&self.message, 1);
+        buf
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use crate::config::get_config;
+use crate::proxy::{ProxySocketInfo, ProxySocketState};
+
+/// Interval in which the remaining sessions are counted while draining.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(250);
+/// Time the disconnected sessions are given to deliver the message, forwarded sessions send it once the
+/// client received the packet it is in the middle of.
+const DISCONNECT_GRACE: Duration = Duration::from_secs(2);
+/// Time the signal handler waits for the connection which wakes the listener.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Open connections, which are disconnected when they do not drain in time.
+static SESSIONS: Lazy<Mutex<HashMap<u64, Arc<Mutex<ProxySocketInfo>>>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// New players are kicked and the server list shows `shutdown_message` while the proxy shuts down.
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Connection registered for the shutdown, it is removed from the registry when dropped.
+pub struct Session {
+    id: u64
+}
+
+impl Session {
+    pub fn register(socket_info: &Arc<Mutex<ProxySocketInfo>>) -> Session {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        SESSIONS.lock().unwrap().insert(id, socket_info.clone());
+        Session {
+            id
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        SESSIONS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Sessions are copied out of the registry before they are locked, a session may be locked for as long as
+/// it takes to connect its origin and new connections must not wait for that to register.
+fn registered_sessions() -> Vec<Arc<Mutex<ProxySocketInfo>>> {
+    SESSIONS.lock().unwrap().values().cloned().collect()
+}
+
+/// Number of sessions of players, forwarded to an origin or about to be.
+fn active_sessions() -> usize {
+    registered_sessions().iter()
+        .filter(|session| matches!(session.lock().unwrap().state, ProxySocketState::Forward | ProxySocketState::Login))
+        .count()
+}
+
+fn disconnect_sessions(message: &str) {
+    for session in registered_sessions() {
+        session.lock().unwrap().shut_down(message);
+    }
+}
+
+/// Waits until the active sessions end, they are disconnected with the message once the deadline passes or
+/// `interrupted` tells another signal was received. Returns whether the sessions drained in time.
+fn drain_sessions<F>(deadline: Instant, message: &str, mut interrupted: F) -> bool
+where
+    F: FnMut() -> bool
+{
+    loop {
+        let remaining = active_sessions();
+        if remaining == 0 {
+            info!("all sessions drained");
+            return true
+        }
+        if Instant::now() >= deadline {
+            info!("shutdown_timeout passed, disconnecting {} sessions", remaining);
+            disconnect_sessions(message);
+            return false
+        }
+        if interrupted() {
+            warn!("received another signal, disconnecting {} sessions", remaining);
+            disconnect_sessions(message);
+            return false
+        }
+        sleep(DRAIN_INTERVAL);
+    }
+}
+
+/// Connects to the listener blocked in `accept`, so it sees the shutdown and stops accepting connections.
+fn wake_listener(listen_addr: SocketAddr) {
+    let addr = match listen_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::from((Ipv4Addr::LOCALHOST, listen_addr.port())),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::from((Ipv6Addr::LOCALHOST, listen_addr.port())),
+        _ => listen_addr
+    };
+    _ = TcpStream::connect_timeout(&addr, WAKE_TIMEOUT);
+}
+
+/// Shuts the proxy down on SIGTERM or SIGINT once the sessions drain or `shutdown_timeout` passes,
+/// a second signal shuts it down immediately. The listener at `listen_addr` stops accepting connections right away.
+pub fn spawn_signal_handler(listen_addr: SocketAddr) {
+    let mut signals = Signals::new([SIGTERM, SIGINT]).expect("Failed to register signal handlers");
+    spawn(move || {
+        let Some(signal) = signals.forever().next() else {
+            return
+        };
+        SHUTTING_DOWN.store(true, Ordering::Relaxed);
+        wake_listener(listen_addr);
+        let config = get_config();
+        let deadline = Instant::now() + Duration::from_millis(config.settings.shutdown_timeout);
+        info!("received signal {}, draining {} sessions", signal, active_sessions());
+        
+        if !drain_sessions(deadline, &config.settings.shutdown_message, || signals.pending().next().is_some()) {
+            let deadline = Instant::now() + DISCONNECT_GRACE;
+            while !SESSIONS.lock().unwrap().is_empty() && Instant::now() < deadline {
+                sleep(DRAIN_INTERVAL);
+            }
+        }
+        exit(0);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::phase::PhaseTracker;
+    use super::*;
+    
+    fn forwarded_session() -> (TcpStream, Arc<Mutex<ProxySocketInfo>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        let mut socket_info = ProxySocketInfo::new(addr, listener.local_addr().unwrap(), stream);
+        socket_info.state = ProxySocketState::Forward;
+        socket_info.phase = Some(PhaseTracker::new(765));
+        (client, Arc::new(Mutex::new(socket_info)))
+    }
+    
+    #[test]
+    fn check_drain() {
+        let (mut client, socket_info) = forwarded_session();
+        let session = Session::register(&socket_info);
+        let ended = spawn(move || {
+            sleep(DRAIN_INTERVAL);
+            drop(session);
+        });
+        assert!(drain_sessions(Instant::now() + Duration::from_secs(10), "Proxy is shutting down", || false));
+        ended.join().unwrap();
+        assert!(socket_info.lock().unwrap().state == ProxySocketState::Forward);
+        
+        // sessions still open at the deadline are disconnected with the message
+        let _session = Session::register(&socket_info);
+        assert!(!drain_sessions(Instant::now() + DRAIN_INTERVAL, "Proxy is shutting down", || false));
+        assert!(socket_info.lock().unwrap().state == ProxySocketState::Closed);
+        assert_eq!(active_sessions(), 0);
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(received[1], 0);
+        assert!(String::from_utf8_lossy(&received).contains("Proxy is shutting down"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::client_packets::HandshakePacket;
+use crate::config::BUFFER_SIZE;
+use crate::packet::{MinecraftPacket, PacketParseError};
+use crate::reader::CursoredVarDataReader;
+use crate::resolver::connect_origin;
+
+/// Maximum status response length, a protocol string holds up to 32767 UTF-16 code units.
+const STATUS_RESPONSE_LIMIT: usize = 32767 * 3 + 16;
+
+pub struct CachedStatus {
+    pub json: String,
+    pub fetched: Instant
+}
+
+/// Identifies a status response of a backend. The response tells the client whether its version is supported,
+/// and backends may answer differently depending on the forwarded hostname and port or a PROXY header.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StatusKey {
+    pub origin: String,
+    pub protocol: u32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub proxy_protocol: bool
+}
+
+impl StatusKey {
+    /// Key of the status a backend answers to the forwarded handshake, which is preceded by a PROXY header or not.
+    pub fn new(origin: &str, handshake: &[u8], proxy_protocol: bool) -> StatusKey {
+        let handshake_packet = MinecraftPacket::parse_packet(handshake.to_vec()).ok()
+            .and_then(|(mut packet, _)| HandshakePacket::try_from(&mut packet).ok());
+        let (protocol, server_address, server_port) = match handshake_packet {
+            Some(packet) => (packet.protocol_version, packet.server_address, packet.server_port),
+            None => (0, String::new(), 0)
+        };
+        StatusKey {
+            origin: origin.to_string(),
+            protocol,
+            server_address,
+            server_port,
+            proxy_protocol
+        }
+    }
+}
+
+/// Status responses of backends, bounded by `cache_size` entries.
+pub struct StatusCache {
+    entries: HashMap<StatusKey, CachedStatus>,
+    /// Statuses being fetched, other connections wait for the fetch instead of connecting to the origin
+    fetching: HashSet<StatusKey>
+}
+
+impl StatusCache {
+    pub fn new() -> StatusCache {
+        StatusCache {
+            entries: HashMap::new(),
+            fetching: HashSet::new()
+        }
+    }
+    
+    pub fn get(&self, key: &StatusKey, ttl: Duration) -> Option<String> {
+        self.entries.get(key)
+            .filter(|entry| entry.fetched.elapsed() < ttl)
+            .map(|entry| entry.json.clone())
+    }
+    
+    pub fn insert(&mut self, key: StatusKey, json: String, capacity: usize, ttl: Duration) {
+        if capacity == 0 {
+            return
+        }
+        
+        if !self.entries.contains_key(&key) && self.entries.len() >= capacity {
+            self.entries.retain(|_, entry| entry.fetched.elapsed() < ttl);
+            while self.entries.len() >= capacity {
+                let oldest = self.entries.iter()
+                    .min_by_key(|(_, entry)| entry.fetched)
+                    .map(|(key, _)| key.clone());
+                match oldest {
+                    Some(key) => self.entries.remove(&key),
+                    None => break
+                };
+            }
+        }
+        
+        self.entries.insert(key, CachedStatus {
+            json,
+            fetched: Instant::now()
+        });
+    }
+}
+
+static STATUS_CACHE: Lazy<Mutex<StatusCache>> = Lazy::new(|| {
+    Mutex::new(StatusCache::new())
+});
+
+/// Notified whenever a fetch of a status finishes.
+static STATUS_FETCHED: Condvar = Condvar::new();
+
+/// Returns the cached status for the key, or fetches it by `fetch` when it has expired.
+/// Connections asking for a status which is being fetched wait for that fetch, they get an error when it fails.
+pub fn cached_status<F>(key: StatusKey, capacity: usize, ttl: Duration, fetch: F) -> Result<String, Error>
+where
+    F: FnOnce() -> Result<String, Error>
+{
+    if capacity == 0 {
+        return fetch()
+    }
+    
+    let mut cache = STATUS_CACHE.lock().unwrap();
+    if cache.fetching.contains(&key) {
+        cache = STATUS_FETCHED.wait_while(cache, |cache| cache.fetching.contains(&key)).unwrap();
+        return cache.get(&key, ttl)
+            .ok_or_else(|| Error::other("status could not be fetched by another connection"))
+    }
+    if let Some(json) = cache.get(&key, ttl) {
+        return Ok(json)
+    }
+    cache.fetching.insert(key.clone());
+    drop(cache);
+    
+    let result = fetch();
+    let mut cache = STATUS_CACHE.lock().unwrap();
+    cache.fetching.remove(&key);
+    if let Ok(json) = &result {
+        cache.insert(key, json.clone(), capacity, ttl);
+    }
+    STATUS_FETCHED.notify_all();
+    result
+}
+
+/// Queries the status of a backend by sending the given handshake followed by a Status Request.
+/// The handshake may be preceded by a PROXY protocol header when the backend expects one.
+pub fn fetch_status(origin: &str, handshake: &[u8], timeout: Duration) -> Result<String, Error> {
+    let (mut stream, _) = connect_origin(origin, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    
+    let request = MinecraftPacket::empty();
+    let mut data = handshake.to_vec();
+    data.extend_from_slice(&request.serialize());
+    stream.write_all(&data)?;
+    
+    let mut buf: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+    let chunk = &mut [0u8; BUFFER_SIZE];
+    loop {
+        let len = stream.read(chunk)?;
+        if len == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "backend closed connection"))
+        }
+        buf.extend_from_slice(&chunk[0..len]);
+        
+        match MinecraftPacket::parse_packet(buf.clone()) {
+            Ok((mut packet, _)) => {
+                if packet.id != 0 {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("unexpected packet {}", packet.id)))
+                }
+                return packet.read_string()
+                    .ok_or(Error::new(ErrorKind::InvalidData, "malformed status response"))
+            }
+            Err(PacketParseError::LengthMismatch) => {
+                if buf.len() > STATUS_RESPONSE_LIMIT {
+                    return Err(Error::new(ErrorKind::InvalidData, "status response too long"))
+                }
+            }
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, format!("{:?}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread::{scope, sleep};
+    use crate::packet::MinecraftProtocolState;
+    use super::*;
+    
+    fn status_key(origin: &str, protocol: u32) -> StatusKey {
+        StatusKey {
+            origin: origin.to_string(),
+            protocol,
+            server_address: String::from("mc.example.org"),
+            server_port: 25565,
+            proxy_protocol: false
+        }
+    }
+    
+    #[test]
+    fn check_cache_bounds() {
+        let ttl = Duration::from_secs(60);
+        let mut cache = StatusCache::new();
+        cache.insert(status_key("a", 765), String::from("1"), 2, ttl);
+        cache.insert(status_key("b", 765), String::from("2"), 2, ttl);
+        cache.insert(status_key("c", 765), String::from("3"), 2, ttl);
+        
+        assert_eq!(cache.get(&status_key("a", 765), ttl), None);
+        assert_eq!(cache.get(&status_key("b", 765), ttl), Some(String::from("2")));
+        assert_eq!(cache.get(&status_key("c", 765), ttl), Some(String::from("3")));
+        assert_eq!(cache.get(&status_key("c", 47), ttl), None);
+        assert_eq!(cache.get(&status_key("c", 765), Duration::ZERO), None);
+    }
+    
+    #[test]
+    fn check_key() {
+        let handshake = MinecraftPacket::from(HandshakePacket {
+            protocol_version: 765,
+            server_address: String::from("mc.example.org"),
+            server_port: 25565,
+            next_state: MinecraftProtocolState::STATUS
+        }).serialize();
+        let key = StatusKey::new("a", &handshake, false);
+        assert_eq!(key, status_key("a", 765));
+        
+        let ttl = Duration::from_secs(60);
+        let mut cache = StatusCache::new();
+        cache.insert(key.clone(), String::from("1"), 16, ttl);
+        assert_eq!(cache.get(&StatusKey { server_address: String::from("lobby.example.org"), ..key.clone() }, ttl), None);
+        assert_eq!(cache.get(&StatusKey { server_port: 25566, ..key.clone() }, ttl), None);
+        assert_eq!(cache.get(&StatusKey { proxy_protocol: true, ..key.clone() }, ttl), None);
+        assert_eq!(cache.get(&key, ttl), Some(String::from("1")));
+    }
+    
+    #[test]
+    fn check_single_fetch() {
+        let ttl = Duration::from_secs(60);
+        let fetches = AtomicU32::new(0);
+        let fetch = || {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(50));
+            Ok(String::from("{}"))
+        };
+        scope(|s| {
+            let handles: Vec<_> = (0..8).map(|_| s.spawn(|| cached_status(status_key("single-fetch-test", 765), 16, ttl, fetch))).collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap().unwrap(), "{}");
+            }
+        });
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        
+        cached_status(status_key("single-fetch-test", 47), 16, ttl, fetch).unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
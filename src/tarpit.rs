@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use log::debug;
+use once_cell::sync::Lazy;
+use crate::config::ConfigSettings;
+
+/// Interval in which the held connections are checked for their release.
+const RELEASE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Rejected connections held open without any response, each until its release time. They are all
+/// held by a single thread, so a tarpitted client occupies neither a connection thread nor a `clients_limit` slot.
+pub struct Tarpit {
+    sockets: VecDeque<(TcpStream, Instant)>
+}
+
+impl Tarpit {
+    pub fn new() -> Tarpit {
+        Tarpit {
+            sockets: VecDeque::new()
+        }
+    }
+    
+    /// Holds the connection until `release`, it is closed right away when `limit` connections are held already.
+    pub fn hold(&mut self, stream: TcpStream, release: Instant, limit: usize) -> bool {
+        if self.sockets.len() >= limit {
+            _ = stream.shutdown(Shutdown::Both);
+            return false
+        }
+        self.sockets.push_back((stream, release));
+        true
+    }
+    
+    /// Closes the connections whose time is up, returns the number of connections still held.
+    pub fn release(&mut self, now: Instant) -> usize {
+        self.sockets.retain(|(stream, release)| {
+            if *release > now {
+                return true
+            }
+            _ = stream.shutdown(Shutdown::Both);
+            false
+        });
+        self.sockets.len()
+    }
+}
+
+static TARPIT: Lazy<Mutex<Tarpit>> = Lazy::new(|| {
+    Mutex::new(Tarpit::new())
+});
+
+/// Hands a rejected connection over to the tarpit, which holds it for `tarpit_time` without reading from it.
+pub fn tarpit(stream: TcpStream, addr: SocketAddr, settings: &ConfigSettings) {
+    let release = Instant::now() + Duration::from_millis(settings.tarpit_time);
+    if TARPIT.lock().unwrap().hold(stream, release, settings.tarpit_limit) {
+        debug!("[{}] holding connection in the tarpit", addr);
+    } else {
+        debug!("[{}] tarpit_limit exceeded, dropping connection", addr);
+    }
+}
+
+/// Closes the tarpitted connections once their time is up.
+pub fn spawn_tarpit() {
+    spawn(|| loop {
+        sleep(RELEASE_INTERVAL);
+        _ = TARPIT.lock().unwrap().release(Instant::now());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read};
+    use std::net::TcpListener;
+    use super::*;
+    
+    /// Connected client and server side of a local connection.
+    fn connection(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+    
+    #[test]
+    fn check_hold_and_release() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut tarpit = Tarpit::new();
+        let now = Instant::now();
+        let (mut held, server) = connection(&listener);
+        assert!(tarpit.hold(server, now + Duration::from_secs(30), 1));
+        let (mut refused, server) = connection(&listener);
+        assert!(!tarpit.hold(server, now + Duration::from_secs(30), 1));
+        
+        let mut buf = [0u8; 16];
+        assert_eq!(refused.read(&mut buf).unwrap(), 0);
+        // nothing is sent to a held client
+        assert!(matches!(held.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+        
+        assert_eq!(tarpit.release(now + Duration::from_secs(29)), 1);
+        assert_eq!(tarpit.release(now + Duration::from_secs(30)), 0);
+        assert_eq!(held.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use once_cell::sync::Lazy;
+use log::warn;
+use regex::Regex;
+use crate::config::{BlocklistEntry, Config, ConfigEndpoint, ConfigRewrite, BUFFER_SIZE};
+use crate::forwarding::ForwardingMode;
+use crate::geoip::open_database;
+use crate::hostname::{is_valid_pattern, parse_origin};
+use crate::resolver::parse_dns_server;
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{[^}]*\}").unwrap());
+
+/// Invalid or suspicious value of the config, located by its YAML path such as `endpoints[2].origin`.
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub path: String,
+    pub message: String
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+struct Validator {
+    errors: Vec<ConfigError>
+}
+
+impl Validator {
+    fn error(&mut self, path: String, message: String) {
+        self.errors.push(ConfigError {
+            path,
+            message
+        });
+    }
+    
+    fn check(&mut self, valid: bool, path: &str, message: &str) {
+        if !valid {
+            self.error(path.to_string(), message.to_string());
+        }
+    }
+    
+    /// Placeholders are expanded by the labels the hostname of the endpoint captured.
+    fn check_placeholders(&mut self, path: &str, template: &str, captures: &[String]) {
+        for placeholder in PLACEHOLDER.find_iter(template) {
+            let name = &placeholder.as_str()[1..(placeholder.len() - 1)];
+            if !captures.iter().any(|capture| capture == name) {
+                self.error(path.to_string(), format!("placeholder {} is not captured by the hostname", placeholder.as_str()));
+            }
+        }
+    }
+    
+    /// Origin is a host with an optional port, hostnames are resolved when the origin is connected.
+    /// Placeholders are replaced by a sample label for the check.
+    fn check_origin(&mut self, path: String, origin: &str, captures: &[String]) {
+        self.check_placeholders(&path, origin, captures);
+        let sample = PLACEHOLDER.replace_all(origin, "0");
+        if let Err(e) = parse_origin(&sample) {
+            self.error(path, e.replace(sample.as_ref(), origin));
+        }
+    }
+    
+    fn check_endpoint(&mut self, path: &str, endpoint: &ConfigEndpoint, config: &Config) {
+        let captures = endpoint.capture_names();
+        if let Some(origin) = &endpoint.origin {
+            self.check_origin(format!("{}.origin", path), origin, &captures);
+        }
+        for (i, origin) in endpoint.origins.iter().enumerate() {
+            self.check_origin(format!("{}.origins[{}]", path, i), &origin.address, &captures);
+            self.check(origin.weight > 0, &format!("{}.origins[{}].weight", path, i), "weight must be positive");
+        }
+        if let Some(origin) = &endpoint.forge_origin {
+            self.check_origin(format!("{}.forge_origin", path), origin, &captures);
+        }
+        for (i, origin) in endpoint.forge_origins.iter().enumerate() {
+            self.check_origin(format!("{}.forge_origins[{}]", path, i), &origin.address, &captures);
+            self.check(origin.weight > 0, &format!("{}.forge_origins[{}].weight", path, i), "weight must be positive");
+        }
+        if let Some(ConfigRewrite::Value(host)) = &endpoint.rewrite_host {
+            self.check_placeholders(&format!("{}.rewrite_host", path), host, &captures);
+        }
+        
+        if let Some(limit) = endpoint.concurrent_limit {
+            self.check(
+                limit <= config.settings.clients_limit,
+                &format!("{}.concurrent_limit", path),
+                "concurrent_limit is greater than clients_limit"
+            );
+        }
+        self.check(
+            endpoint.forwarding.is_none() || endpoint.allow_unauthenticated_forwarding,
+            &format!("{}.allow_unauthenticated_forwarding", path),
+            "forwarded players are not authenticated, forwarding must be allowed by allow_unauthenticated_forwarding"
+        );
+        if endpoint.forwarding == Some(ForwardingMode::Velocity) {
+            self.check(
+                endpoint.forwarding_secret.as_ref().is_some_and(|secret| !secret.is_empty()),
+                &format!("{}.forwarding_secret", path),
+                "velocity forwarding requires a forwarding_secret"
+            );
+        }
+        let geoip_lists = !endpoint.allow_countries.is_empty() || !endpoint.allow_asns.is_empty()
+            || !endpoint.deny_countries.is_empty() || !endpoint.deny_asns.is_empty();
+        self.check(
+            !geoip_lists || !config.settings.geoip_databases.is_empty(),
+            path,
+            "country and ASN lists require geoip_databases"
+        );
+    }
+}
+
+/// Checks the values which are valid YAML but could not be used by the proxy, returns all errors found.
+pub fn validate(config: &Config) -> Vec<ConfigError> {
+    let mut validator = Validator {
+        errors: Vec::new()
+    };
+    let settings = &config.settings;
+    
+    validator.check(settings.handshake_timeout > 0, "settings.handshake_timeout", "must be positive");
+    validator.check(settings.client_buffer_size >= BUFFER_SIZE, "settings.client_buffer_size", &format!("must be at least {}", BUFFER_SIZE));
+    validator.check(settings.backend_buffer_size >= BUFFER_SIZE, "settings.backend_buffer_size", &format!("must be at least {}", BUFFER_SIZE));
+    validator.check(settings.client_packets_limit > 0, "settings.client_packets_limit", "must be positive");
+    validator.check(settings.clients_limit > 0, "settings.clients_limit", "must be positive");
+    validator.check(
+        settings.concurrent_limit <= settings.clients_limit,
+        "settings.concurrent_limit",
+        "concurrent_limit is greater than clients_limit"
+    );
+    validator.check(
+        settings.ratelimit == 0 || settings.ratelimit_window > 0,
+        "settings.ratelimit_window",
+        "ratelimit requires a positive ratelimit_window"
+    );
+    validator.check(settings.ratelimit_ipv6_prefix <= 128, "settings.ratelimit_ipv6_prefix", "must be at most 128");
+    validator.check(settings.tarpit_time > 0, "settings.tarpit_time", "must be positive");
+    for (i, path) in settings.geoip_databases.iter().enumerate() {
+        if let Err(e) = open_database(path) {
+            validator.error(format!("settings.geoip_databases[{}]", i), e);
+        }
+    }
+    for (i, server) in settings.dns_servers.iter().enumerate() {
+        validator.check(parse_dns_server(server).is_some(), &format!("settings.dns_servers[{}]", i), "must be an address with an optional port");
+    }
+    if let Some(health_check) = &settings.health_check {
+        validator.check(health_check.interval > 0, "settings.health_check.interval", "must be positive");
+        validator.check(health_check.timeout > 0, "settings.health_check.timeout", "must be positive");
+    }
+    
+    // endpoints with the same hostname would shadow each other
+    let mut hostnames: HashMap<String, usize> = HashMap::new();
+    for (i, endpoint) in config.endpoints.iter().enumerate() {
+        let path = format!("endpoints[{}]", i);
+        let name = match (&endpoint.hostname, &endpoint.hostname_regex) {
+            (Some(hostname), _) => Some((format!("{}.hostname", path), hostname.pattern.clone())),
+            (None, Some(regex)) => Some((format!("{}.hostname_regex", path), regex.pattern.clone())),
+            (None, None) => None
+        };
+        match name {
+            Some((name_path, name)) => match hostnames.get(&name) {
+                Some(first) => validator.error(name_path, format!("{} is already used by endpoints[{}]", name, first)),
+                None => _ = hostnames.insert(name, i)
+            },
+            None => validator.error(path.clone(), String::from("endpoint has neither a hostname nor a hostname_regex"))
+        }
+        validator.check_endpoint(&path, endpoint, config);
+    }
+    if let Some(fallback) = &config.fallback {
+        validator.check_endpoint("fallback", fallback, config);
+    }
+    // entries resembling addresses fail to parse, anything else is taken for a hostname
+    for (i, entry) in config.blocklist.iter().enumerate() {
+        if let BlocklistEntry::Hostname(pattern) = entry {
+            validator.check(
+                is_valid_pattern(&pattern.pattern),
+                &format!("blocklist[{}]", i),
+                "must be an address, a network or a hostname"
+            );
+        }
+    }
+    
+    validator.errors
+}
+
+fn hostname_path(index: usize, endpoint: &ConfigEndpoint) -> String {
+    match endpoint.hostname {
+        Some(_) => format!("endpoints[{}].hostname", index),
+        None => format!("endpoints[{}].hostname_regex", index)
+    }
+}
+
+/// Whether the endpoint matches a hostname the other endpoint is meant for, wildcards are tried with a sample label.
+fn overlaps(endpoint: &ConfigEndpoint, other: &ConfigEndpoint) -> bool {
+    let sample = match &other.hostname {
+        Some(hostname) => PLACEHOLDER.replace_all(&hostname.pattern.replace('*', "x"), "x").into_owned(),
+        None => return false
+    };
+    match (&endpoint.hostname, &endpoint.hostname_regex) {
+        (Some(hostname), _) => hostname.captures(&sample).is_some(),
+        (None, Some(regex)) => regex.captures(&sample).is_some(),
+        (None, None) => false
+    }
+}
+
+/// Checks the values which are valid but likely a mistake, such as endpoints whose hostnames overlap.
+/// Exact hostnames take precedence over wildcards and wildcards over regexes, so only one of them gets the connections.
+pub fn find_warnings(config: &Config) -> Vec<ConfigError> {
+    let mut warnings = Vec::new();
+    for (i, first) in config.endpoints.iter().enumerate() {
+        for (j, second) in config.endpoints.iter().enumerate().skip(i + 1) {
+            // identical hostnames are reported as errors
+            if first.name() != second.name() && (overlaps(first, second) || overlaps(second, first)) {
+                warnings.push(ConfigError {
+                    path: hostname_path(j, second),
+                    message: format!("{} overlaps with {} of endpoints[{}]", second.name(), first.name(), i)
+                });
+            }
+        }
+    }
+    warnings
+}
+
+pub fn log_warnings(config: &Config) {
+    for warning in find_warnings(config) {
+        warn!("config {}", warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    
+    #[test]
+    fn check_validation_errors() {
+        let config = test_config(r#"
+settings: { handshake_timeout: 0, client_buffer_size: 1024, ratelimit_window: 0 }
+endpoints:
+  - hostname: play.example.net
+    origin: "10.0.0.1:99999"
+  - hostname: Play.Example.net
+    origins: ["10.0.0.2:25565", "mc.internal", ":25565"]
+    concurrent_limit: 500
+  - hostname: "{sub}.example.net"
+    origin: "10.0.1.{sub}:25565"
+    forwarding: velocity
+  - origin: 10.0.0.3:25565
+  - hostname: "*.example.org"
+    origin: "{2}.internal:25565"
+    rewrite_host: "{sbu}.example.org"
+  - hostname_regex: '(?P<sub>[a-z]+)\.example\.com'
+    origins: ["{sub}.internal", "{1}.internal", "{3}.internal"]
+fallback:
+  origin: 10.0.0.4:25565
+  deny_asns: [64500]
+blocklist: ["203.0.113.0/24", "*.bots example.net"]
+"#);
+        
+        let errors: Vec<String> = validate(&config).iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, vec![
+            "settings.handshake_timeout: must be positive",
+            "settings.client_buffer_size: must be at least 4096",
+            "settings.ratelimit_window: ratelimit requires a positive ratelimit_window",
+            "endpoints[0].origin: origin 10.0.0.1:99999 has an invalid port",
+            "endpoints[1].hostname: play.example.net is already used by endpoints[0]",
+            "endpoints[1].origins[2]: origin :25565 has no host",
+            "endpoints[1].concurrent_limit: concurrent_limit is greater than clients_limit",
+            "endpoints[2].allow_unauthenticated_forwarding: forwarded players are not authenticated, forwarding must be allowed by allow_unauthenticated_forwarding",
+            "endpoints[2].forwarding_secret: velocity forwarding requires a forwarding_secret",
+            "endpoints[3]: endpoint has neither a hostname nor a hostname_regex",
+            "endpoints[4].origin: placeholder {2} is not captured by the hostname",
+            "endpoints[4].rewrite_host: placeholder {sbu} is not captured by the hostname",
+            "endpoints[5].origins[2]: placeholder {3} is not captured by the hostname",
+            "fallback: country and ASN lists require geoip_databases",
+            "blocklist[1]: must be an address, a network or a hostname"
+        ]);
+        
+        let config = test_config("settings: { geoip_databases: [/nonexistent/GeoLite2-ASN.mmdb] }");
+        let paths: Vec<String> = validate(&config).into_iter().map(|error| error.path).collect();
+        assert_eq!(paths, ["settings.geoip_databases[0]"]);
+    }
+    
+    #[test]
+    fn check_overlapping_hostnames() {
+        let config = test_config(r#"
+endpoints:
+  - hostname: "*.example.net"
+    origin: 10.0.0.1:25565
+  - hostname: lobby.example.net
+    origin: 10.0.0.2:25565
+  - hostname: "{sub}.example.net"
+    origin: 10.0.0.3:25565
+  - hostname: play.example.org
+    origin: 10.0.0.4:25565
+  - hostname_regex: 'play\.example\.(org|com)'
+    origin: 10.0.0.5:25565
+"#);
+        let warnings: Vec<String> = find_warnings(&config).iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "endpoints[1].hostname: lobby.example.net overlaps with *.example.net of endpoints[0]",
+            "endpoints[2].hostname: {sub}.example.net overlaps with *.example.net of endpoints[0]",
+            "endpoints[2].hostname: {sub}.example.net overlaps with lobby.example.net of endpoints[1]",
+            r"endpoints[4].hostname_regex: play\.example\.(org|com) overlaps with play.example.org of endpoints[3]"
+        ]);
+        assert_eq!(validate(&config), Vec::new());
+    }
+    
+    #[test]
+    fn check_valid_config() {
+        let config: Config = serde_yaml::from_str(crate::cli::DEFAULT_CONFIG).unwrap();
+        assert_eq!(validate(&config), Vec::new());
+    }
+}
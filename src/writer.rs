@@ -7,7 +7,11 @@ pub trait VarDataWriter {
     
     fn write_u16(&mut self, val: u16, offset: usize);
     
-    fn write_string(&mut self, val: &String, offset: usize) -> usize;
+    fn write_i64(&mut self, val: i64, offset: usize);
+    
+    fn write_string(&mut self, val: &str, offset: usize) -> usize;
+    
+    fn write_utf16_string(&mut self, val: &str, offset: usize) -> usize;
 }
 
 impl VarDataWriter for Vec<u8> {
@@ -84,7 +88,14 @@ impl VarDataWriter for Vec<u8> {
         self[offset + 1] = bytes[1];
     }
     
-    fn write_string(&mut self, val: &String, offset: usize) -> usize {
+    fn write_i64(&mut self, val: i64, offset: usize) {
+        if self.len() < offset + 8 {
+            self.resize(offset + 8, 0);
+        }
+        self[offset..(offset + 8)].copy_from_slice(&val.to_be_bytes());
+    }
+    
+    fn write_string(&mut self, val: &str, offset: usize) -> usize {
         let bytes = val.as_bytes();
         let prefix_len = self.write_int(bytes.len() as i32, offset);
         let total_len = prefix_len + bytes.len();
@@ -94,18 +105,41 @@ impl VarDataWriter for Vec<u8> {
         self[(offset + prefix_len)..(offset + total_len)].copy_from_slice(bytes);
         total_len
     }
+    
+    fn write_utf16_string(&mut self, val: &str, offset: usize) -> usize {
+        let units: Vec<u16> = val.encode_utf16().collect();
+        self.write_u16(units.len() as u16, offset);
+        let total_len = 2 + units.len() * 2;
+        if self.len() < offset + total_len {
+            self.resize(offset + total_len, 0);
+        }
+        for (i, unit) in units.iter().enumerate() {
+            let pos = offset + 2 + i * 2;
+            self[pos..(pos + 2)].copy_from_slice(&unit.to_be_bytes());
+        }
+        total_len
+    }
 }
 
 pub trait CursoredVarDataWriter {
+    #[allow(dead_code)]
     fn reset_cursor(&mut self);
     
     fn write_int(&mut self, val: i32);
     
+    #[allow(dead_code)]
     fn write_long(&mut self, val: i64);
     
     fn write_u16(&mut self, val: u16);
     
-    fn write_string(&mut self, val: &String);
+    fn write_i64(&mut self, val: i64);
+    
+    fn write_string(&mut self, val: &str);
+    
+    fn write_bool(&mut self, val: bool);
+    
+    /// Writes raw bytes without a length prefix.
+    fn write_bytes(&mut self, val: &[u8]);
 }
 
 #[cfg(test)]
@@ -156,6 +190,17 @@ mod tests {
         });
     }
     
+    #[test]
+    fn check_encoding_fixed_long() {
+        let mut vec: Vec<u8> = Vec::new();
+        let nums: [i64; 6] = [ 0, 100, -100, 1712345678901, i64::MIN, i64::MAX ];
+        
+        for num in nums {
+            vec.write_i64(num, 0);
+            assert_eq!(vec.read_i64(0).unwrap(), num);
+        }
+    }
+    
     #[test]
     fn check_encoding_string() {
         let mut vec: Vec<u8> = Vec::new();
@@ -173,4 +218,15 @@ mod tests {
             assert_eq!(*str, decoded);
         });
     }
+    
+    #[test]
+    fn check_encoding_utf16_string() {
+        let mut vec: Vec<u8> = Vec::new();
+        let strings = ["MC|PingHost", "", "§aA Minecraft Server"];
+        
+        for str in strings {
+            vec.write_utf16_string(str, 0);
+            assert_eq!(vec.read_utf16_string(0).unwrap(), (String::from(str), 2 + str.encode_utf16().count() * 2));
+        }
+    }
 }
\ No newline at end of file